@@ -1,27 +1,45 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
+#[cfg(not(test))]
 mod delay;
+#[cfg(not(test))]
 mod usb_io;
+#[cfg(not(test))]
 mod usdhc;
 
+// the host tests only build the parts of the driver that don't touch the
+// hardware, see `usdhc/host.rs`
+#[cfg(test)]
+#[path = "usdhc/host.rs"]
+mod usdhc;
+
+#[cfg(not(test))]
 use bsp::{
     hal::{self, gpio::GPIO},
     Led,
 };
+#[cfg(not(test))]
 use delay::Delay;
 
+#[cfg(not(test))]
 use embedded_hal::{digital::v2::OutputPin, prelude::_embedded_hal_blocking_delay_DelayMs};
+#[cfg(not(test))]
 use hal::iomuxc::consts::U1;
+#[cfg(not(test))]
 use teensy4_bsp as bsp;
+#[cfg(not(test))]
 use teensy4_panic as _;
+#[cfg(not(test))]
 use usdhc::commands::*;
 
+#[cfg(not(test))]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     run();
 }
 
+#[cfg(not(test))]
 fn run() -> ! {
     let mut peripherals = bsp::Peripherals::take().unwrap();
     let pins = bsp::t41::from_pads(peripherals.iomuxc);
@@ -74,7 +92,10 @@ fn run() -> ! {
 
         sd.init_card();
 
-        sd.wait_for_card();
+        if let Err(err) = sd.wait_for_card() {
+            log::error!("command line stuck: {:?}", err);
+            continue;
+        }
 
         let state = sd.get_state();
         log::debug!("state {:b}", state);
@@ -101,30 +122,16 @@ fn run() -> ! {
 
         log::warn!("version 2 {}", version2);
 
-        let orc = if version2 {
-            0x40300000_u32
-        } else {
-            0x00300000_u32
-        };
-
-        let mut retry_counter = 0u32;
-
-        let res = loop {
-            log::debug!("SdAppOpCond now");
-            sd.send_command(SdAppOpCond::new(orc));
-
-            let res = sd.read_response_u32();
-            log::debug!("response: {:b}", res);
-            if (res & 0x80000000) != 0 || retry_counter > 10 {
-                break res;
+        match sd.initialize_card(version2) {
+            Ok(()) => {
+                let mut blocks = [[0u8; usdhc::BLOCK_SIZE]; 4];
+                match sd.read_blocks(0, &mut blocks) {
+                    Ok(()) => log::debug!("mbr signature {:x?}", &blocks[0][510..]),
+                    Err(err) => log::error!("read_blocks {:?}", err),
+                }
             }
-            retry_counter += 1;
-            delay.delay_ms(250u32);
-        };
-
-        log::error!("SdAppOpCond {}", res);
-
-        // sd.set_command(SendExtCsd::new());
+            Err(err) => log::error!("initialize_card {:?}", err),
+        }
 
         let state = sd.get_state();
         log::debug!("state {:b}", state);
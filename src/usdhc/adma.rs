@@ -0,0 +1,284 @@
+//! # Advanced DMA (ADMA2)
+//!
+//! The ADMA2 engine walks a table of 64 bit descriptors in system memory,
+//! the address of the table is programmed into ADMA_SYS_ADDR and the engine
+//! is selected with PROT_CTRL[DMASEL] = `10`.
+//!
+//! | Bits    | Field     | Description                                  |
+//! | ------- | --------- | -------------------------------------------- |
+//! | [63:32] | Address   | 32 bit, word aligned system address          |
+//! | [31:16] | Length    | 16 bit data length of this line              |
+//! | [5:4]   | Act       | `00` nop, `10` transfer data, `11` link      |
+//! | [2]     | Int       | DINT after this line                         |
+//! | [1]     | End       | last line of the table                       |
+//! | [0]     | Valid     | line is valid, ADMA error otherwise          |
+//!
+//! The descriptor construction doesn't touch any register and works on
+//! plain addresses, so it can be used on the host with captured values.
+
+/// Maximum data length of one descriptor line, kept word aligned
+pub const ADMA2_MAX_LENGTH: usize = 0xFFFC;
+
+/// Number of descriptor lines the driver reserves for vectored transfers
+pub const ADMA2_TABLE_LEN: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+/// One line of an ADMA2 descriptor table
+pub struct Adma2Descriptor {
+    /// attributes [15:0] and length [31:16]
+    pub attributes: u32,
+    /// system address of the data
+    pub address: u32,
+}
+
+impl Adma2Descriptor {
+    pub const VALID: u32 = 1 << 0;
+    pub const END: u32 = 1 << 1;
+    pub const INT: u32 = 1 << 2;
+    pub const ACT_NOP: u32 = 0b00 << 4;
+    pub const ACT_TRAN: u32 = 0b10 << 4;
+    pub const ACT_LINK: u32 = 0b11 << 4;
+    const ACT_MASK: u32 = 0b11 << 4;
+
+    /// A valid transfer line for `length` bytes at `address`
+    pub const fn transfer(address: u32, length: u16) -> Self {
+        Self {
+            attributes: ((length as u32) << 16) | Self::ACT_TRAN | Self::VALID,
+            address,
+        }
+    }
+
+    pub fn length(&self) -> u16 {
+        (self.attributes >> 16) as u16
+    }
+
+    pub fn action(&self) -> u32 {
+        self.attributes & Self::ACT_MASK
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.attributes & Self::VALID != 0
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.attributes & Self::END != 0
+    }
+
+    pub fn raises_interrupt(&self) -> bool {
+        self.attributes & Self::INT != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Reasons a list of segments can't be described by an ADMA2 table
+pub enum Adma2TableError {
+    /// no data at all
+    Empty,
+    /// address or length of a segment is not word aligned
+    Unaligned,
+    /// more descriptor lines required than the table can hold
+    TooManyDescriptors,
+}
+
+#[repr(C, align(4))]
+/// ADMA2 descriptor table with room for `N` lines
+pub struct Adma2Table<const N: usize> {
+    descriptors: [Adma2Descriptor; N],
+    used: usize,
+}
+
+impl<const N: usize> Adma2Table<N> {
+    pub const fn new() -> Self {
+        Self {
+            descriptors: [Adma2Descriptor {
+                attributes: 0,
+                address: 0,
+            }; N],
+            used: 0,
+        }
+    }
+
+    /// Fills the table from `(address, length)` segments.
+    ///
+    /// Segments longer than `ADMA2_MAX_LENGTH` are split over several lines,
+    /// the last line is marked with End and Int. Empty segments are skipped.
+    pub fn build<I>(&mut self, segments: I) -> Result<&[Adma2Descriptor], Adma2TableError>
+    where
+        I: IntoIterator<Item = (u32, usize)>,
+    {
+        self.used = 0;
+        for (address, length) in segments {
+            if address % 4 != 0 || length % 4 != 0 {
+                return Err(Adma2TableError::Unaligned);
+            }
+            let mut offset = 0;
+            while offset < length {
+                if self.used == N {
+                    return Err(Adma2TableError::TooManyDescriptors);
+                }
+                let chunk = (length - offset).min(ADMA2_MAX_LENGTH);
+                self.descriptors[self.used] =
+                    Adma2Descriptor::transfer(address + offset as u32, chunk as u16);
+                self.used += 1;
+                offset += chunk;
+            }
+        }
+
+        match self.used {
+            0 => Err(Adma2TableError::Empty),
+            n => {
+                self.descriptors[n - 1].attributes |= Adma2Descriptor::END | Adma2Descriptor::INT;
+                Ok(&self.descriptors[..n])
+            }
+        }
+    }
+
+    /// The lines filled by the last `build`
+    pub fn descriptors(&self) -> &[Adma2Descriptor] {
+        &self.descriptors[..self.used]
+    }
+
+    /// System address of the table for ADMA_SYS_ADDR
+    pub fn address(&self) -> u32 {
+        self.descriptors.as_ptr() as u32
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// State of the ADMA engine when the error occurred, ADMA_ERR_STATUS[ADMAES]
+pub enum AdmaState {
+    /// ST_STOP, stop DMA (the error happened on the previous line)
+    Stop,
+    /// ST_FDS, fetching the descriptor
+    FetchDescriptor,
+    /// ST_CADR, changing the address (link line)
+    ChangeAddress,
+    /// ST_TFR, transferring the data
+    Transfer,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// ADMA_ERR_STATUS, read after an ADMA error (INT_STATUS[DMAE])
+pub struct AdmaErrorStatus(pub u32);
+
+impl AdmaErrorStatus {
+    pub fn state(&self) -> AdmaState {
+        match self.0 & 0b11 {
+            0b00 => AdmaState::Stop,
+            0b01 => AdmaState::FetchDescriptor,
+            0b10 => AdmaState::ChangeAddress,
+            _ => AdmaState::Transfer,
+        }
+    }
+
+    /// ADMALME, the total descriptor length doesn't match BLKSIZE * BLKCNT
+    pub fn length_mismatch(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    /// ADMADCE, a descriptor without the Valid bit was fetched
+    pub fn descriptor_error(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x2020_0000;
+
+    #[test]
+    fn transfer_line_attributes() {
+        let line = Adma2Descriptor::transfer(BASE, 512);
+        assert_eq!(line.attributes, 512 << 16 | 0b10 << 4 | 1);
+        assert_eq!(line.address, BASE);
+        assert_eq!(line.length(), 512);
+        assert_eq!(line.action(), Adma2Descriptor::ACT_TRAN);
+        assert!(line.is_valid());
+        assert!(!line.is_end());
+        assert!(!line.raises_interrupt());
+    }
+
+    #[test]
+    fn end_and_int_only_on_last_line() {
+        let mut table = Adma2Table::<4>::new();
+        let lines = table
+            .build([(BASE, 512), (BASE + 0x1000, 1024), (BASE + 0x4000, 4)])
+            .unwrap();
+        assert_eq!(lines.len(), 3);
+        for line in &lines[..2] {
+            assert!(line.is_valid());
+            assert!(!line.is_end());
+            assert!(!line.raises_interrupt());
+        }
+        assert!(lines[2].is_end());
+        assert!(lines[2].raises_interrupt());
+        assert_eq!(lines[2].attributes, 4 << 16 | Adma2Descriptor::ACT_TRAN | 0b111);
+        assert_eq!(table.descriptors().len(), 3);
+    }
+
+    #[test]
+    fn splits_64k_segment() {
+        let mut table = Adma2Table::<4>::new();
+        let lines = table.build([(BASE, 64 * 1024)]).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].address, BASE);
+        assert_eq!(lines[0].length() as usize, ADMA2_MAX_LENGTH);
+        assert_eq!(lines[1].address, BASE + ADMA2_MAX_LENGTH as u32);
+        assert_eq!(lines[1].length(), 4);
+        assert!(!lines[0].is_end());
+        assert!(lines[1].is_end());
+    }
+
+    #[test]
+    fn splits_long_segment_between_short_ones() {
+        let mut table = Adma2Table::<8>::new();
+        let lines = table
+            .build([(BASE, 8), (BASE + 0x100, 3 * ADMA2_MAX_LENGTH), (BASE + 0x4_0000, 8)])
+            .unwrap();
+        let lengths: [usize; 5] = core::array::from_fn(|i| lines[i].length() as usize);
+        assert_eq!(lengths, [8, ADMA2_MAX_LENGTH, ADMA2_MAX_LENGTH, ADMA2_MAX_LENGTH, 8]);
+        assert_eq!(lines[3].address, BASE + 0x100 + 2 * ADMA2_MAX_LENGTH as u32);
+    }
+
+    #[test]
+    fn empty_segments_are_skipped() {
+        let mut table = Adma2Table::<2>::new();
+        let lines = table.build([(BASE, 0), (BASE + 0x10, 16), (BASE + 0x20, 0)]).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].is_end());
+        assert_eq!(table.build([(BASE, 0)]), Err(Adma2TableError::Empty));
+    }
+
+    #[test]
+    fn rejects_unaligned_segments() {
+        let mut table = Adma2Table::<2>::new();
+        assert_eq!(table.build([(BASE + 2, 16)]), Err(Adma2TableError::Unaligned));
+        assert_eq!(table.build([(BASE, 18)]), Err(Adma2TableError::Unaligned));
+    }
+
+    #[test]
+    fn too_many_segments() {
+        let mut table = Adma2Table::<2>::new();
+        let segments = [(BASE, 4), (BASE + 0x10, 4), (BASE + 0x20, 4)];
+        assert_eq!(table.build(segments), Err(Adma2TableError::TooManyDescriptors));
+        // a single segment split beyond the table
+        assert_eq!(
+            table.build([(BASE, 2 * ADMA2_MAX_LENGTH + 4)]),
+            Err(Adma2TableError::TooManyDescriptors)
+        );
+        // the table is usable again afterwards
+        assert_eq!(table.build([(BASE, 4), (BASE + 0x10, 4)]).map(|l| l.len()), Ok(2));
+    }
+
+    #[test]
+    fn adma_error_status() {
+        let status = AdmaErrorStatus(0b1101);
+        assert_eq!(status.state(), AdmaState::FetchDescriptor);
+        assert!(status.length_mismatch());
+        assert!(status.descriptor_error());
+        assert_eq!(AdmaErrorStatus(0).state(), AdmaState::Stop);
+    }
+}
@@ -0,0 +1,323 @@
+//! # Block I/O
+//!
+//! Reading and writing 512 byte blocks of SD memory cards.
+//!
+//! Every operation has an `_async` version that can be awaited from an
+//! executor, the blocking version runs it with `executor::block_on`.
+
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    commands,
+    error::WriteError,
+    executor::block_on,
+    irq,
+    registers::CardStatus,
+    transfer::{StopMode, Transfer},
+    Error, USdhc,
+};
+
+/// Block length used for all block commands, SDHC/SDXC cards only support 512 bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Converts a block number into the data address argument.
+    ///
+    /// SDSC cards are byte addressed, SDHC/SDXC cards are block addressed.
+    /// Fails with `Error::InvalidArgument` if the byte address of an SDSC
+    /// card doesn't fit into the argument.
+    pub(crate) fn block_address(&self, lba: u32) -> Result<u32, Error> {
+        if self.high_capacity {
+            Ok(lba)
+        } else {
+            lba.checked_mul(BLOCK_SIZE as u32).ok_or(Error::InvalidArgument)
+        }
+    }
+
+    /// Reads a single block with CMD17
+    pub fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), Error> {
+        block_on(self.read_block_async(lba, block))
+    }
+
+    /// Async version of `read_block`
+    pub async fn read_block_async(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), Error> {
+        let transfer = Transfer::read(BLOCK_SIZE as u32, 1, StopMode::None);
+        self.read_data_async(
+            commands::ReadSingleBlock::new(self.block_address(lba)?),
+            &transfer,
+            block,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Reads consecutive blocks starting at `start_lba` with CMD18.
+    ///
+    /// If the card supports CMD23 (see SCR) the block count is announced
+    /// upfront, otherwise the uSDHC terminates the transfer with an auto
+    /// CMD12. On an error in the middle of the stream the transfer is stopped
+    /// and the card is returned to the transfer state before the error is
+    /// reported.
+    pub fn read_blocks(&mut self, start_lba: u32, blocks: &mut [[u8; BLOCK_SIZE]]) -> Result<(), Error> {
+        block_on(self.read_blocks_async(start_lba, blocks))
+    }
+
+    /// Async version of `read_blocks`
+    pub async fn read_blocks_async(
+        &mut self,
+        start_lba: u32,
+        blocks: &mut [[u8; BLOCK_SIZE]],
+    ) -> Result<(), Error> {
+        match blocks.len() {
+            0 => return Ok(()),
+            1 => return self.read_block_async(start_lba, &mut blocks[0]).await,
+            n if n > 65535 => return Err(Error::InvalidArgument),
+            _ => {}
+        }
+
+        let count = blocks.len() as u32;
+        let address = self.block_address(start_lba)?;
+        // CMD23 applies to the next command only, see `irq::BusGuard`
+        let _bus = irq::BusGuard::claim();
+        let stop = self.set_block_count(count).await?;
+
+        // Safety: [[u8; 512]] is a contiguous array of bytes
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(blocks.as_mut_ptr() as *mut u8, blocks.len() * BLOCK_SIZE)
+        };
+        let transfer = Transfer::read(BLOCK_SIZE as u32, count, stop);
+        self.read_data_async(
+            commands::ReadMultipleBlock::new(address),
+            &transfer,
+            buffer,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Writes a single block with CMD24
+    ///
+    /// Returns after the card finished programming the block.
+    pub fn write_block(&mut self, lba: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), WriteError> {
+        block_on(self.write_block_async(lba, block))
+    }
+
+    /// Async version of `write_block`
+    pub async fn write_block_async(&mut self, lba: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), WriteError> {
+        let address = self.block_address(lba)?;
+        // held until ACMD22 reported a failure
+        let _bus = irq::BusGuard::claim();
+        let transfer = Transfer::write(BLOCK_SIZE as u32, 1, StopMode::None);
+        let result = self
+            .write_data_async(
+                commands::WriteBlock::new(address),
+                &transfer,
+                block,
+            )
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(cause) => Err(self.write_error(cause).await),
+        }
+    }
+
+    /// Writes consecutive blocks starting at `start_lba` with CMD25.
+    ///
+    /// The transfer is terminated like in `read_blocks`. If the write fails,
+    /// the returned error holds the number of blocks the card committed
+    /// before the failure (ACMD22). With `set_pre_erase` the blocks are
+    /// pre-erased with ACMD23 first.
+    pub fn write_blocks(&mut self, start_lba: u32, blocks: &[[u8; BLOCK_SIZE]]) -> Result<(), WriteError> {
+        block_on(self.write_blocks_async(start_lba, blocks))
+    }
+
+    /// Async version of `write_blocks`
+    pub async fn write_blocks_async(
+        &mut self,
+        start_lba: u32,
+        blocks: &[[u8; BLOCK_SIZE]],
+    ) -> Result<(), WriteError> {
+        match blocks.len() {
+            0 => return Ok(()),
+            1 => return self.write_block_async(start_lba, &blocks[0]).await,
+            n if n > 65535 => return Err(Error::InvalidArgument.into()),
+            _ => {}
+        }
+
+        let count = blocks.len() as u32;
+        let address = self.block_address(start_lba)?;
+        // ACMD23, CMD23 and CMD25 go out back to back, see `irq::BusGuard`
+        let _bus = irq::BusGuard::claim();
+        self.pre_erase_blocks(count).await;
+        let stop = self.set_block_count(count).await?;
+
+        // Safety: [[u8; 512]] is a contiguous array of bytes
+        let buffer =
+            unsafe { core::slice::from_raw_parts(blocks.as_ptr() as *const u8, blocks.len() * BLOCK_SIZE) };
+        let transfer = Transfer::write(BLOCK_SIZE as u32, count, stop);
+        let result = self
+            .write_data_async(
+                commands::WriteMultipleBlock::new(address),
+                &transfer,
+                buffer,
+            )
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(cause) => Err(self.write_error(cause).await),
+        }
+    }
+
+    /// Reads consecutive blocks starting at `start_lba` into a list of buffers.
+    ///
+    /// With `TransferMode::Adma2` the data goes straight into the buffers
+    /// (one descriptor line per buffer), otherwise it's scattered by the CPU.
+    /// The total length has to be a multiple of `BLOCK_SIZE`.
+    pub fn read_vectored(&mut self, start_lba: u32, buffers: &mut [&mut [u8]]) -> Result<(), Error> {
+        block_on(self.read_vectored_async(start_lba, buffers))
+    }
+
+    /// Async version of `read_vectored`
+    pub async fn read_vectored_async(&mut self, start_lba: u32, buffers: &mut [&mut [u8]]) -> Result<(), Error> {
+        let count = Self::vectored_block_count(buffers.iter().map(|b| b.len()))?;
+        if count == 0 {
+            return Ok(());
+        }
+        let address = self.block_address(start_lba)?;
+        if count == 1 {
+            let transfer = Transfer::read(BLOCK_SIZE as u32, 1, StopMode::None);
+            self.read_data_vectored_async(commands::ReadSingleBlock::new(address), &transfer, buffers)
+                .await?;
+            return Ok(());
+        }
+
+        let _bus = irq::BusGuard::claim();
+        let stop = self.set_block_count(count).await?;
+        let transfer = Transfer::read(BLOCK_SIZE as u32, count, stop);
+        self.read_data_vectored_async(commands::ReadMultipleBlock::new(address), &transfer, buffers)
+            .await?;
+        Ok(())
+    }
+
+    /// Writes a list of buffers to consecutive blocks starting at `start_lba`.
+    ///
+    /// Counterpart of `read_vectored`, failures are reported like in `write_blocks`.
+    pub fn write_vectored(&mut self, start_lba: u32, buffers: &[&[u8]]) -> Result<(), WriteError> {
+        block_on(self.write_vectored_async(start_lba, buffers))
+    }
+
+    /// Async version of `write_vectored`
+    pub async fn write_vectored_async(&mut self, start_lba: u32, buffers: &[&[u8]]) -> Result<(), WriteError> {
+        let count = Self::vectored_block_count(buffers.iter().map(|b| b.len()))?;
+        if count == 0 {
+            return Ok(());
+        }
+        let address = self.block_address(start_lba)?;
+        let _bus = irq::BusGuard::claim();
+        let result = if count == 1 {
+            let transfer = Transfer::write(BLOCK_SIZE as u32, 1, StopMode::None);
+            self.write_data_vectored_async(commands::WriteBlock::new(address), &transfer, buffers)
+                .await
+        } else {
+            self.pre_erase_blocks(count).await;
+            let stop = self.set_block_count(count).await?;
+            let transfer = Transfer::write(BLOCK_SIZE as u32, count, stop);
+            self.write_data_vectored_async(commands::WriteMultipleBlock::new(address), &transfer, buffers)
+                .await
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(cause) => Err(self.write_error(cause).await),
+        }
+    }
+
+    fn vectored_block_count(lengths: impl Iterator<Item = usize>) -> Result<u32, Error> {
+        let total: usize = lengths.sum();
+        if total % BLOCK_SIZE != 0 || total / BLOCK_SIZE > 65535 {
+            return Err(Error::InvalidArgument);
+        }
+        Ok((total / BLOCK_SIZE) as u32)
+    }
+
+    /// Announces `count` blocks with CMD23 if the card supports it, returns
+    /// how the following multi-block transfer is stopped.
+    async fn set_block_count(&mut self, count: u32) -> Result<StopMode, Error> {
+        let stop = self.multi_block_stop_mode();
+        if stop == StopMode::PreDefined {
+            let status = self.execute_async(commands::SetBlockCount::new(count)).await?;
+            CardStatus(status).check()?;
+        }
+        Ok(stop)
+    }
+
+    /// Sends ACMD23 if pre-erasing is enabled.
+    ///
+    /// The hint is optional, a card rejecting it (no response or an error
+    /// status) disables pre-erasing and the write continues without it.
+    async fn pre_erase_blocks(&mut self, count: u32) {
+        if !self.pre_erase {
+            return;
+        }
+        let result = match self.execute_async(commands::SetWrBlkEraseCount::new(count)).await {
+            Ok(status) => CardStatus(status).check().map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::warn!("ACMD23 rejected {:?}, pre-erase disabled", err);
+            self.pre_erase = false;
+            if err == Error::CommandTimeout && self.reset_data_line().is_err() {
+                return;
+            }
+            // ILLEGAL_COMMAND is reported in the next response, CMD13 clears
+            // it so CMD23/CMD25 don't fail on it
+            if let Err(err) = self.execute_async(commands::SendStatus::new(self.rca)).await {
+                log::error!("CMD13 failed {:?}", err);
+            }
+        }
+    }
+
+    /// Number of blocks written without errors by the last write command (ACMD22)
+    pub fn num_written_blocks(&mut self) -> Result<u32, Error> {
+        block_on(self.num_written_blocks_async())
+    }
+
+    /// Async version of `num_written_blocks`
+    pub async fn num_written_blocks_async(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0u8; 4];
+        let transfer = Transfer::read(4, 1, StopMode::None);
+        self.read_data_async(commands::SendNumWrSectors::new(), &transfer, &mut bytes)
+            .await?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    async fn write_error(&mut self, cause: Error) -> WriteError {
+        let written_blocks = match self.num_written_blocks_async().await {
+            Ok(count) => count,
+            Err(err) => {
+                log::error!("ACMD22 failed {:?}", err);
+                0
+            }
+        };
+        WriteError {
+            cause,
+            written_blocks,
+        }
+    }
+
+    /// CMD23 if the SCR reports support for it, auto CMD12 otherwise
+    pub(crate) fn multi_block_stop_mode(&self) -> StopMode {
+        match self.scr {
+            Some(scr) if scr.supports_cmd23() => StopMode::PreDefined,
+            _ => StopMode::AutoCmd12,
+        }
+    }
+}
@@ -1,65 +1,259 @@
+//! # SDIO extended I/O transfers (CMD53)
+//!
+//! A CMD53 moves either 1..=512 bytes (byte mode) or 1..=511 blocks of the
+//! function block size (block mode). Arbitrary lengths are split into block
+//! mode runs followed by a byte mode tail. The CRC of the command and the
+//! data is generated and checked by the uSDHC.
+
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    commands,
+    transfer::{StopMode, Transfer},
+    Error, USdhc,
+};
+
+/// Maximum block count of a block mode CMD53 (0 would be an infinite transfer)
+pub const IO_MAX_BLOCK_COUNT: usize = 511;
+/// Maximum length of a byte mode CMD53
+pub const IO_MAX_BYTE_COUNT: usize = 512;
+/// Maximum function block size (FBR I/O block size)
+pub const IO_MAX_BLOCK_SIZE: usize = 2048;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Register address handling of a CMD53 (OP code)
+pub enum IoAddressMode {
+    /// Every byte goes to the same register, e.g. a FIFO
+    Fixed,
+    /// The register address is incremented for every byte
+    Incrementing,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// One CMD53 of a transfer
+pub struct IoChunk {
+    /// register address of the first byte
+    pub address: u32,
+    /// offset of the chunk in the data
+    pub offset: usize,
+    /// byte count (byte mode) or block count (block mode)
+    pub count: usize,
+    /// block size of the block mode, the byte count in byte mode
+    pub block_size: usize,
+    pub block_mode: bool,
+}
+
+impl IoChunk {
+    pub fn len(&self) -> usize {
+        if self.block_mode {
+            self.count * self.block_size
+        } else {
+            self.count
+        }
+    }
+
+    /// count field of the argument, 512 bytes are encoded as 0
+    fn count_arg(&self) -> u16 {
+        (self.count % IO_MAX_BYTE_COUNT) as u16
+    }
+}
+
+enum IoBuffer<'data> {
+    Read(&'data mut [u8]),
+    Write(&'data [u8]),
+}
+
+/// Transfer of an arbitrary length to/from the register space of an SDIO function
 pub struct DataTransfer<'data> {
-    data: &'data [u8],
-    length: usize,
+    data: IoBuffer<'data>,
+    function: u8,
+    address: u32,
+    mode: IoAddressMode,
     max_block_size: usize,
 }
 
 impl<'data> DataTransfer<'data> {
-    pub fn new(data: &'data [u8], length: usize, max_block_size: usize) -> Self {
+    /// Reads `data.len()` bytes from `address` of `function`.
+    ///
+    /// `max_block_size` is the block size configured for the function,
+    /// 0 limits the transfer to byte mode.
+    pub fn read(function: u8, address: u32, mode: IoAddressMode, data: &'data mut [u8], max_block_size: usize) -> Self {
         Self {
-            data,
-            length,
+            data: IoBuffer::Read(data),
+            function,
+            address,
+            mode,
             max_block_size,
         }
     }
 
-    fn send_data(&mut self) {
-        let data_lng = self.data.len();
-        let large_block_count = data_lng / self.max_block_size;
-
-        let chunks = self.data.chunks(self.max_block_size);
-        let last_lng = 0;
-        for c in chunks.into_iter() {
-            let lng = c.len();
-            if lng != last_lng {
-                if lng == self.max_block_size {
-                    // send new CMD 53 + large_block_count
-                } else {
-                    // send new CMD 53 + 1 block
+    /// Writes `data` to `address` of `function`, see `read`
+    pub fn write(function: u8, address: u32, mode: IoAddressMode, data: &'data [u8], max_block_size: usize) -> Self {
+        Self {
+            data: IoBuffer::Write(data),
+            function,
+            address,
+            mode,
+            max_block_size,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.data {
+            IoBuffer::Read(data) => data.len(),
+            IoBuffer::Write(data) => data.len(),
+        }
+    }
+
+    /// The CMD53s needed for the transfer
+    pub fn chunks(&self) -> IoChunks {
+        IoChunks {
+            address: self.address,
+            offset: 0,
+            remaining: self.len(),
+            block_size: self.max_block_size,
+            increment: self.mode == IoAddressMode::Incrementing,
+        }
+    }
+
+    /// Runs the transfer, one CMD53 per chunk.
+    ///
+    /// Stops at the first failing chunk, the data of the previous chunks
+    /// has been transferred.
+    pub fn send_data<M, CMD, CLK, D0, D1, D2, D3>(
+        &mut self,
+        usdhc: &mut USdhc<M, CMD, CLK, D0, D1, D2, D3>,
+    ) -> Result<(), Error>
+    where
+        M: Unsigned,
+        CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+        CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+        D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+        D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+        D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+        D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+    {
+        if self.function > 7 || self.max_block_size > IO_MAX_BLOCK_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let increment = self.mode == IoAddressMode::Incrementing;
+        let function = self.function;
+        for chunk in self.chunks() {
+            let data = chunk.offset..chunk.offset + chunk.len();
+            match &mut self.data {
+                IoBuffer::Read(buffer) => {
+                    let transfer = Transfer::read(chunk.block_size as u32, block_count(&chunk), StopMode::Io);
+                    let cmd = commands::IoReadExtended::new(
+                        function,
+                        chunk.address,
+                        chunk.block_mode,
+                        increment,
+                        chunk.count_arg(),
+                    );
+                    usdhc
+                        .read_data(cmd, &transfer, &mut buffer[data])
+                        .map_err(|err| io_abort(usdhc, function, &chunk, err))?;
+                }
+                IoBuffer::Write(buffer) => {
+                    let transfer = Transfer::write(chunk.block_size as u32, block_count(&chunk), StopMode::Io);
+                    let cmd = commands::IoWriteExtended::new(
+                        function,
+                        chunk.address,
+                        chunk.block_mode,
+                        increment,
+                        chunk.count_arg(),
+                    );
+                    usdhc
+                        .write_data(cmd, &transfer, &buffer[data])
+                        .map_err(|err| io_abort(usdhc, function, &chunk, err))?;
                 }
             }
-            // send Block data (c)
         }
+        Ok(())
     }
+}
 
-    fn calc_cmd_crc() -> u8 {
-        // The CRC polynomials for the CMD are as follows:
-        //
-        // Generator polynomial: G(x) = x^7 + x^3 + 1
-        // M(x) = (first bit) * x^n + (second bit) * x^(n-1) +...+ (last bit) * x^0
-        // CRC[6:0] = Remainder [(M(x) * x^7) / G(x)]
-
-        //7bit
-        0
+/// Stops the transfer of `function` through the CCCR I/O abort register
+/// after a failed block mode chunk, returns `err`
+fn io_abort<M, CMD, CLK, D0, D1, D2, D3>(
+    usdhc: &mut USdhc<M, CMD, CLK, D0, D1, D2, D3>,
+    function: u8,
+    chunk: &IoChunk,
+    err: Error,
+) -> Error
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    if chunk.block_mode {
+        if let Err(abort) = usdhc.execute(commands::IoAbort::new(function)) {
+            log::warn!("io abort failed {:?}", abort);
+        }
     }
-    fn calc_data_crc() -> u16 {
-        // The CRC polynomials for the data are as follows:
-        //
-        // Generator polynomial: G(x) = x^16 + x^12 + x^5 + 1
-        // M(x) = (first bit) * x^n + (second bit) * x^(n-1) +...+ (last bit) * x^0
-        // CRC[15:0] = Remainder [(M(x) * x^16) / G(x)]
-        0
+    err
+}
+
+/// BLK_ATT block count of a chunk, a byte mode chunk is one block
+fn block_count(chunk: &IoChunk) -> u32 {
+    if chunk.block_mode {
+        chunk.count as u32
+    } else {
+        1
     }
 }
 
-/// The access bits for the EXT_CSD access modes are listed in the following enumeration.
-pub enum ExtCsd {
-    /// The command set is changed according to the Cmd Set field of the argument.
-    CommandSet = 0b00,
-    /// The bits in the pointed byte are set, according to the bits set to 1 in the Value field.
-    SetBits = 0b01,
-    /// The bits in the pointed byte are cleared, according to the bits set to 1 in the Value field.
-    ClearBits = 0b10,
-    /// The Value field is written into the pointed byte.
-    WriteByte = 0b11,
+/// Splits a transfer into block mode runs of up to `IO_MAX_BLOCK_COUNT`
+/// blocks and a byte mode tail shorter than a block
+pub struct IoChunks {
+    address: u32,
+    offset: usize,
+    remaining: usize,
+    block_size: usize,
+    increment: bool,
+}
+
+impl Iterator for IoChunks {
+    type Item = IoChunk;
+
+    fn next(&mut self) -> Option<IoChunk> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let chunk = if self.block_size != 0 && self.remaining >= self.block_size {
+            let count = (self.remaining / self.block_size).min(IO_MAX_BLOCK_COUNT);
+            IoChunk {
+                address: self.address,
+                offset: self.offset,
+                count,
+                block_size: self.block_size,
+                block_mode: true,
+            }
+        } else {
+            let limit = match self.block_size {
+                0 => IO_MAX_BYTE_COUNT,
+                size => size.min(IO_MAX_BYTE_COUNT),
+            };
+            let count = self.remaining.min(limit);
+            IoChunk {
+                address: self.address,
+                offset: self.offset,
+                count,
+                block_size: count,
+                block_mode: false,
+            }
+        };
+
+        let len = chunk.len();
+        self.offset += len;
+        self.remaining -= len;
+        if self.increment {
+            self.address += len as u32;
+        }
+        Some(chunk)
+    }
 }
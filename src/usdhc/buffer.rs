@@ -7,9 +7,13 @@
 //!
 //! The buffer is used as a temporary storage for transferring data between the host system
 //! and the card. The watermark levels for read and write are both configurable and can
-//! range between 1 (`BUFFER_MIN_WATERMARK`) to 128 (`BUFFER_MAX_WATERMARK`) words. The burst
+//! range between 1 (`BUFFER_MIN_WATERMARK`) to 128 (`BUFFER_MAX_WATERMARK`) words, the
+//! driver only accepts levels that divide the 128 word buffer. The burst
 //! lengths for read and write are also configurable and can range between
 //! 1 (`BUFFER_MIN_BURST_LENGTH`) to 31 (`BUFFER_MAX_BURST_LENGTH`) words.
+//! They are configured with `Watermark`.
+
+use super::{commands::DataDirection, Error};
 
 /// The watermark levels (**RD_WML**) for read and write are both
 /// configurable and can range between 1 `BUFFER_MIN_WATERMARK`
@@ -84,3 +88,168 @@ the number of words set in the WR_WML register. If the buffer is empty and the h
 system does not write data in time, uSDHC stops the CLK to avoid the data buffer
 underrun situation.
  */
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Transfer mode used for the data phase of block commands
+pub enum TransferMode {
+    /// The CPU moves every word through DATA_BUFF_ACC_PORT (BRR/BWR polling)
+    CpuPolling,
+    /// Simple DMA: the uSDHC moves the data from/to the address in DS_ADDR.
+    ///
+    /// The transfer stops at every SDMA buffer boundary (512 KiB) with DINT
+    /// set until DS_ADDR is written again. Buffers that are not 4 byte
+    /// aligned (cache line aligned for reads from cached memory) go through
+    /// the bounce buffer, see `cache.rs`, or fall back to `CpuPolling` if
+    /// they don't fit.
+    Sdma,
+    /// Advanced DMA: the uSDHC walks an ADMA2 descriptor table (see `adma.rs`),
+    /// so a transfer can be scattered over several word aligned buffers.
+    Adma2,
+    /// The internal DMA stays off and an eDMA channel serves the uSDHC DMA
+    /// request at every watermark (see `edma.rs`). Falls back to `CpuPolling`
+    /// if no channel is configured.
+    ExternalDma,
+}
+
+/// SDMA/ADMA require a word aligned system address
+pub const DMA_ALIGNMENT: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Watermark levels and burst lengths programmed into WTMK_LVL, in words.
+///
+/// - CPU polling: BRR/BWR fires once per watermark and the CPU moves a whole
+///   watermark per interrupt. A high level means fewer interrupts, a low
+///   level starts the data earlier and keeps the buffer from stalling SD_CLK.
+/// - Internal DMA: a burst on the AHB bus is cut at the burst length, the
+///   watermark boundary and the 1 KiB boundary. Bursts of 8 or 16 words give
+///   the best bus utilization, longer bursts block other masters longer.
+/// - External eDMA: one minor loop moves one watermark, the transfer length
+///   has to be a multiple of it.
+///
+/// The levels are powers of two, so they divide the 128 word buffer. The
+/// level used for a transfer is lowered until it divides the block, see
+/// `level_for`.
+pub struct Watermark {
+    read_level: u8,
+    write_level: u8,
+    read_burst: u8,
+    write_burst: u8,
+}
+
+impl Watermark {
+    /// Validates the levels against `BUFFER_MIN/MAX_WATERMARK`, they have to
+    /// be powers of two, and the burst lengths against
+    /// `BUFFER_MIN/MAX_BURST_LENGTH`
+    pub fn new(read_level: u8, write_level: u8, read_burst: u8, write_burst: u8) -> Result<Self, Error> {
+        let level = |level: u8| level <= BUFFER_MAX_WATERMARK && level.is_power_of_two();
+        let burst = BUFFER_MIN_BURST_LENGTH..=BUFFER_MAX_BURST_LENGTH;
+        if !level(read_level) || !level(write_level) || !burst.contains(&read_burst) || !burst.contains(&write_burst) {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(Self {
+            read_level,
+            write_level,
+            read_burst,
+            write_burst,
+        })
+    }
+
+    /// RD_WML
+    pub fn read_level(&self) -> u8 {
+        self.read_level
+    }
+
+    /// WR_WML
+    pub fn write_level(&self) -> u8 {
+        self.write_level
+    }
+
+    /// RD_BRST_LEN
+    pub fn read_burst(&self) -> u8 {
+        self.read_burst
+    }
+
+    /// WR_BRST_LEN
+    pub fn write_burst(&self) -> u8 {
+        self.write_burst
+    }
+
+    /// Watermark in words for blocks of `block_size` bytes in `direction`.
+    ///
+    /// The configured level is lowered to the largest power of two that
+    /// divides the words of a block, so BRR/BWR fires for small blocks (SCR,
+    /// status registers) and no watermark is split between two blocks.
+    pub fn level_for(&self, direction: DataDirection, block_size: u32) -> u32 {
+        let level = match direction {
+            DataDirection::Write => self.write_level,
+            _ => self.read_level,
+        };
+        let words = block_size.div_ceil(4).max(1);
+        (level as u32).min(1 << words.trailing_zeros())
+    }
+}
+
+impl Default for Watermark {
+    /// Reset value of WTMK_LVL: 16 word levels, 8 word bursts
+    fn default() -> Self {
+        Self {
+            read_level: 16,
+            write_level: 16,
+            read_burst: 8,
+            write_burst: 8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_divisors_of_the_buffer() {
+        for level in [1, 2, 4, 8, 16, 32, 64, 128] {
+            assert!(Watermark::new(level, level, 8, 8).is_ok(), "{}", level);
+        }
+    }
+
+    #[test]
+    fn rejects_levels_that_split_the_buffer() {
+        for level in [0, 3, 24, 100, 129, 255] {
+            assert_eq!(Watermark::new(level, 16, 8, 8), Err(Error::InvalidArgument), "{}", level);
+            assert_eq!(Watermark::new(16, level, 8, 8), Err(Error::InvalidArgument), "{}", level);
+        }
+    }
+
+    #[test]
+    fn rejects_burst_lengths_out_of_range() {
+        assert_eq!(Watermark::new(16, 16, 0, 8), Err(Error::InvalidArgument));
+        assert_eq!(Watermark::new(16, 16, 8, 32), Err(Error::InvalidArgument));
+        assert!(Watermark::new(16, 16, 1, 31).is_ok());
+    }
+
+    #[test]
+    fn level_of_the_direction_for_full_blocks() {
+        let watermark = Watermark::new(128, 32, 8, 8).unwrap();
+        assert_eq!(watermark.level_for(DataDirection::Read, 512), 128);
+        assert_eq!(watermark.level_for(DataDirection::Write, 512), 32);
+    }
+
+    #[test]
+    fn level_is_lowered_to_small_blocks() {
+        let watermark = Watermark::default();
+        // SD Status, SCR, ACMD22
+        assert_eq!(watermark.level_for(DataDirection::Read, 64), 16);
+        assert_eq!(watermark.level_for(DataDirection::Read, 8), 2);
+        assert_eq!(watermark.level_for(DataDirection::Read, 4), 1);
+    }
+
+    #[test]
+    fn level_divides_odd_blocks() {
+        let watermark = Watermark::default();
+        // CMD53 byte mode: 12 and 40 bytes, 6 bytes are moved in 2 words
+        assert_eq!(watermark.level_for(DataDirection::Write, 12), 1);
+        assert_eq!(watermark.level_for(DataDirection::Write, 40), 2);
+        assert_eq!(watermark.level_for(DataDirection::Write, 6), 2);
+        assert_eq!(watermark.level_for(DataDirection::Write, 1), 1);
+    }
+}
@@ -0,0 +1,83 @@
+//! # Data bus width
+//!
+//! The card and PROT_CTRL[DTW] have to agree on the number of data lines,
+//! the card is switched first, then the controller.
+
+use teensy4_bsp::{hal::ral, pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{commands, registers::CardStatus, BusWidth, CardMode, Error, USdhc};
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Current card mode
+    pub fn mode(&self) -> CardMode {
+        self.mode
+    }
+
+    /// Data transfer width the controller uses (PROT_CTRL[DTW])
+    pub fn bus_width(&self) -> BusWidth {
+        match ral::read_reg!(ral::usdhc, self.usdhc, PROT_CTRL, DTW) {
+            0b00 => BusWidth::One,
+            0b01 => BusWidth::Four,
+            _ => BusWidth::Eight,
+        }
+    }
+
+    pub(crate) fn set_data_transfer_width(&mut self, width: BusWidth) {
+        ral::modify_reg!(ral::usdhc, self.usdhc, PROT_CTRL, DTW: width.dtw());
+    }
+
+    /// Switches an SD memory card to 1 or 4 data lines with ACMD6.
+    ///
+    /// 4 bit needs SD_BUS_WIDTHS support in the SCR and 4 wired lines (see
+    /// `Builder::bus_width`). The new width is verified by reading the SCR
+    /// again, on a mismatch the card is switched back to 1 bit.
+    pub fn set_bus_width(&mut self, width: BusWidth) -> Result<(), Error> {
+        let scr = self.scr.ok_or(Error::Unsupported)?;
+        match width {
+            BusWidth::One => {}
+            BusWidth::Four if scr.supports_4bit() && self.wired_bus_width >= BusWidth::Four => {}
+            _ => return Err(Error::Unsupported),
+        }
+        if width == self.bus_width() {
+            return Ok(());
+        }
+
+        self.switch_sd_bus_width(width)?;
+        match self.read_scr() {
+            Ok(read) if read == scr => Ok(()),
+            result => {
+                log::warn!("{:?} bus verification failed", width);
+                self.switch_sd_bus_width(BusWidth::One)?;
+                match result {
+                    Err(err) => Err(err),
+                    Ok(_) => Err(Error::DataCrc),
+                }
+            }
+        }
+    }
+
+    fn switch_sd_bus_width(&mut self, width: BusWidth) -> Result<(), Error> {
+        let arg = match width {
+            BusWidth::Four => 0b10,
+            _ => 0b00,
+        };
+        let status = self.execute(commands::SetBusWidth::new(arg))?;
+        CardStatus(status).check()?;
+        self.set_data_transfer_width(width);
+        self.mode = match width {
+            BusWidth::Four => CardMode::Sd4,
+            _ => CardMode::Sd1,
+        };
+        log::debug!("bus width {:?}", width);
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! # D-cache coherency for DMA buffers
+//!
+//! The Cortex-M7 caches OCRAM and external memory (write-back by default),
+//! while the uSDHC internal DMA and the eDMA access the memory directly.
+//!
+//! - before the DMA reads memory (card write) dirty lines are cleaned
+//! - before the DMA writes memory (card read) the lines are invalidated, so
+//!   no dirty line is evicted over the new data, and again afterwards since
+//!   the core may have speculatively refilled them during the transfer
+//!
+//! Invalidating works on whole 32 byte lines, a read buffer has to start and
+//! end on a line boundary or the neighbouring data would be discarded. Other
+//! buffers go through the `BounceBuffer`. ITCM and DTCM are not cached.
+
+/// Cache line size of the Cortex-M7 D-cache
+pub const CACHE_LINE: usize = 32;
+
+/// Size of the DMA bounce buffer
+#[cfg(not(feature = "save_memory"))]
+pub const BOUNCE_BUFFER_LEN: usize = 4096;
+#[cfg(feature = "save_memory")]
+pub const BOUNCE_BUFFER_LEN: usize = 512;
+
+#[repr(C, align(32))]
+/// Cache line aligned buffer for DMA transfers with unaligned caller slices
+pub struct BounceBuffer(pub [u8; BOUNCE_BUFFER_LEN]);
+
+impl BounceBuffer {
+    pub const fn new() -> Self {
+        Self([0; BOUNCE_BUFFER_LEN])
+    }
+}
+
+/// ITCM (0x0000_0000) and DTCM (0x2000_0000) are tightly coupled, never cached
+fn is_cacheable(address: usize) -> bool {
+    !(address < 0x0008_0000 || (0x2000_0000..0x2008_0000).contains(&address))
+}
+
+fn needs_maintenance(address: usize, len: usize) -> bool {
+    len != 0 && is_cacheable(address) && cortex_m::peripheral::SCB::dcache_enabled()
+}
+
+/// True if the DMA can write into the buffer without touching other data
+/// in the same cache lines
+pub fn is_read_safe(address: usize, len: usize) -> bool {
+    !needs_maintenance(address, len) || (address % CACHE_LINE == 0 && len % CACHE_LINE == 0)
+}
+
+/// Writes dirty lines back to memory before the DMA reads them
+pub fn clean(address: usize, len: usize) {
+    if needs_maintenance(address, len) {
+        // Safety: cache maintenance by address doesn't conflict with other
+        // users of the SCB
+        let mut scb = unsafe { cortex_m::Peripherals::steal() }.SCB;
+        scb.clean_dcache_by_address(address, len);
+    }
+}
+
+/// Discards the cached lines of a buffer the DMA writes into
+///
+/// # Safety
+///
+/// The buffer has to be line aligned (`is_read_safe`), otherwise data of
+/// the neighbouring variables is lost.
+pub unsafe fn invalidate(address: usize, len: usize) {
+    if needs_maintenance(address, len) {
+        let mut scb = cortex_m::Peripherals::steal().SCB;
+        scb.invalidate_dcache_by_address(address, len);
+    }
+}
@@ -0,0 +1,170 @@
+//! # Card identification
+//!
+//! Moves an SD memory card from the idle state (after CMD0/CMD8) through
+//! ACMD41 and the optional 1.8V switch into the transfer state and reads
+//! the registers the block layer depends on.
+
+use teensy4_bsp::{hal::ral, pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    block::BLOCK_SIZE,
+    commands,
+    executor::block_on,
+    registers::{CardStatus, Csd, Ocr, Scr, SdStatus},
+    transfer::{StopMode, Transfer},
+    BusWidth, CardMode, Error, USdhc, ARM_CLOCK_HZ,
+};
+
+/// Time the card may take to leave the busy state of ACMD41
+const OP_COND_TIMEOUT_MS: u32 = 1_000;
+/// Delay between two ACMD41
+const OP_COND_POLL_MS: u32 = 10;
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Relative card address assigned with CMD3
+    pub fn rca(&self) -> u16 {
+        self.rca
+    }
+
+    /// SCR of the card, read during `identify_card`
+    pub fn scr(&self) -> Option<Scr> {
+        self.scr
+    }
+
+    /// CSD of the card, read during `identify_card`
+    pub fn csd(&self) -> Option<Csd> {
+        self.csd
+    }
+
+    /// Initializes the card after CMD0 and CMD8 and identifies it.
+    ///
+    /// `version2` is true if the card answered CMD8, only those cards are
+    /// asked for high capacity (HCS) and 1.8V signaling (S18R, if the host
+    /// supports it). ACMD41 is repeated until the card is ready, fails with
+    /// `Error::Timeout` after 1 s. A card accepting 1.8V is switched
+    /// before CMD2 (`switch_to_1v8`), if that fails the card has to be
+    /// power cycled. Continues with `identify_card`.
+    pub fn initialize_card(&mut self, version2: bool) -> Result<(), Error> {
+        let mut argument = Ocr::VDD_32_34;
+        if version2 {
+            argument |= Ocr::CCS;
+        }
+        let request_1v8 = version2 && self.host_supports_1v8();
+        // CMD55 goes to RCA 0 until CMD3 assigned one
+        self.rca = 0;
+
+        let mut waited_ms = 0;
+        let ocr = loop {
+            let op_cond = commands::SdAppOpCond::new(argument);
+            let ocr = Ocr(self.execute(if request_1v8 { op_cond.with_s18r() } else { op_cond })?);
+            if ocr.is_ready() {
+                break ocr;
+            }
+            if waited_ms >= OP_COND_TIMEOUT_MS {
+                log::error!("card didn't finish its power up");
+                return Err(Error::Timeout);
+            }
+            cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000 * OP_COND_POLL_MS);
+            waited_ms += OP_COND_POLL_MS;
+        };
+        log::debug!("ocr {:x}", ocr.0);
+
+        if request_1v8 {
+            match self.switch_to_1v8(ocr.0) {
+                Ok(()) | Err(Error::Unsupported) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.identify_card(ocr.high_capacity())
+    }
+
+    /// Identifies and selects the card after ACMD41 reported ready.
+    ///
+    /// `high_capacity` is the CCS bit of the ACMD41 response.
+    ///
+    /// CMD2 -> CMD3 -> CMD9 -> CMD7 -> CMD16 -> ACMD51
+    ///
+    /// A password locked card is left in the transfer state and reported
+    /// with `Error::Locked`, the SCR is read once it's unlocked.
+    pub fn identify_card(&mut self, high_capacity: bool) -> Result<(), Error> {
+        block_on(self.identify_card_async(high_capacity))
+    }
+
+    /// Async version of `identify_card`
+    pub async fn identify_card_async(&mut self, high_capacity: bool) -> Result<(), Error> {
+        self.high_capacity = high_capacity;
+
+        self.execute_async(commands::AllSendCid::new()).await?;
+
+        let resp = self.execute_async(commands::SetSendRelativeAddr::new(0)).await?;
+        self.rca = (resp >> 16) as u16;
+        log::debug!("rca {:x}", self.rca);
+
+        self.execute_async(commands::SendCsd::new(self.rca)).await?;
+        self.csd = Some(Csd::from_response([
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP0),
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP1),
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP2),
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP3),
+        ]));
+        log::debug!("csd {:x}", self.csd.unwrap_or_default().0);
+
+        let status = self.execute_async(commands::SelectDeselectCard::new(self.rca)).await?;
+        CardStatus(status).check()?;
+        self.set_data_transfer_width(BusWidth::One);
+        self.mode = CardMode::Sd1;
+        let status = self
+            .execute_async(commands::SetBlocklen::new(BLOCK_SIZE as u32))
+            .await?;
+        let status = CardStatus(status).check()?;
+
+        self.scr = None;
+        self.locked = status.is_set(CardStatus::CARD_IS_LOCKED);
+        if self.locked {
+            log::warn!("card is locked");
+            return Err(Error::Locked);
+        }
+
+        self.scr = Some(self.read_scr_async().await?);
+        log::debug!("scr {:x}", self.scr.unwrap_or_default().0);
+
+        Ok(())
+    }
+
+    /// Reads the SD Configuration Register with ACMD51
+    pub fn read_scr(&mut self) -> Result<Scr, Error> {
+        block_on(self.read_scr_async())
+    }
+
+    /// Async version of `read_scr`
+    pub async fn read_scr_async(&mut self) -> Result<Scr, Error> {
+        let mut bytes = [0u8; 8];
+        let transfer = Transfer::read(8, 1, StopMode::None);
+        self.read_data_async(commands::SendScr::new(), &transfer, &mut bytes)
+            .await?;
+        Ok(Scr::from_bytes(bytes))
+    }
+
+    /// Reads the SD Status with ACMD13
+    pub fn read_sd_status(&mut self) -> Result<SdStatus, Error> {
+        block_on(self.read_sd_status_async())
+    }
+
+    /// Async version of `read_sd_status`
+    pub async fn read_sd_status_async(&mut self) -> Result<SdStatus, Error> {
+        let mut bytes = [0u8; 64];
+        let transfer = Transfer::read(64, 1, StopMode::None);
+        self.read_data_async(commands::SendSdStatus::new(), &transfer, &mut bytes)
+            .await?;
+        Ok(SdStatus(bytes))
+    }
+}
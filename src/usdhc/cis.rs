@@ -0,0 +1,458 @@
+//! # SDIO Card Information Structure (CIS)
+//!
+//! The CIS is a chain of tuples in the common register space, one for the
+//! card (pointer in CCCR 0x09..=0x0B) and one per function (FBR 0x09..=0x0B).
+//!
+//! | Byte | Field                                         |
+//! | ---- | --------------------------------------------- |
+//! | 0    | tuple code, 0x00 NULL (no link), 0xFF END     |
+//! | 1    | link, number of body bytes, 0xFF end of chain |
+//! | 2..  | body                                          |
+//!
+//! The module doesn't depend on the uSDHC: the decoders work on bytes and
+//! `read_chain` walks the chain through a byte read function, which
+//! `USdhc::read_cis` implements with CMD52.
+
+pub const CISTPL_NULL: u8 = 0x00;
+pub const CISTPL_VERS_1: u8 = 0x15;
+pub const CISTPL_MANFID: u8 = 0x20;
+pub const CISTPL_FUNCID: u8 = 0x21;
+pub const CISTPL_FUNCE: u8 = 0x22;
+pub const CISTPL_END: u8 = 0xFF;
+
+/// Offset of the CIS pointer in the CCCR (function 0) and the FBRs
+pub const CIS_POINTER: u32 = 0x09;
+/// Last address of the CIS area in the common register space
+const CIS_AREA_END: u32 = 0x1_7FFF;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// One tuple of a CIS chain
+pub struct CisTuple<'a> {
+    pub code: u8,
+    pub body: &'a [u8],
+}
+
+impl<'a> CisTuple<'a> {
+    /// Vendor specific tuples use the codes 0x80..=0x8F
+    pub fn is_vendor(&self) -> bool {
+        (0x80..=0x8F).contains(&self.code)
+    }
+
+    pub fn manfid(&self) -> Option<ManfId> {
+        match (self.code, self.body) {
+            (CISTPL_MANFID, [m0, m1, c0, c1, ..]) => Some(ManfId {
+                manufacturer: u16::from_le_bytes([*m0, *m1]),
+                card: u16::from_le_bytes([*c0, *c1]),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn funcid(&self) -> Option<FuncId> {
+        match (self.code, self.body) {
+            (CISTPL_FUNCID, [function, sysinit, ..]) => Some(FuncId {
+                function: *function,
+                sysinit: *sysinit,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn funce(&self) -> Option<FuncE> {
+        if self.code != CISTPL_FUNCE {
+            return None;
+        }
+        match self.body {
+            // TPLFE_TYPE 0x00: function 0 extension
+            [0x00, b0, b1, speed, ..] => Some(FuncE::Common {
+                max_block_size: u16::from_le_bytes([*b0, *b1]),
+                max_tran_speed: TranSpeed(*speed),
+            }),
+            // TPLFE_TYPE 0x01: function 1..7 extension, MAX_BLK_SIZE at 0x0C
+            [0x01, function_info, std_io_rev, rest @ ..] if rest.len() >= 11 => Some(FuncE::Function {
+                function_info: *function_info,
+                std_io_rev: *std_io_rev,
+                serial_number: u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]),
+                max_block_size: u16::from_le_bytes([rest[9], rest[10]]),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn vers_1(&self) -> Option<Vers1<'a>> {
+        match (self.code, self.body) {
+            (CISTPL_VERS_1, [major, minor, strings @ ..]) => Some(Vers1 {
+                major: *major,
+                minor: *minor,
+                strings,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the tuples of a CIS chain, stops at CISTPL_END, at a link
+/// of 0xFF or at a tuple that is cut off
+pub struct Cis<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cis<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn manfid(&self) -> Option<ManfId> {
+        Cis::new(self.bytes).find_map(|t| t.manfid())
+    }
+
+    pub fn funcid(&self) -> Option<FuncId> {
+        Cis::new(self.bytes).find_map(|t| t.funcid())
+    }
+
+    pub fn funce(&self) -> Option<FuncE> {
+        Cis::new(self.bytes).find_map(|t| t.funce())
+    }
+
+    pub fn vers_1(&self) -> Option<Vers1<'a>> {
+        Cis::new(self.bytes).find_map(|t| t.vers_1())
+    }
+
+    /// Vendor specific tuples (0x80..=0x8F)
+    pub fn vendor_tuples(&self) -> impl Iterator<Item = CisTuple<'a>> {
+        Cis::new(self.bytes).filter(|t| t.is_vendor())
+    }
+}
+
+impl<'a> Iterator for Cis<'a> {
+    type Item = CisTuple<'a>;
+
+    fn next(&mut self) -> Option<CisTuple<'a>> {
+        loop {
+            match self.bytes {
+                [CISTPL_NULL, rest @ ..] => self.bytes = rest,
+                [CISTPL_END, ..] | [_, 0xFF, ..] | [] | [_] => {
+                    self.bytes = &[];
+                    return None;
+                }
+                [code, link, rest @ ..] => {
+                    let link = *link as usize;
+                    if rest.len() < link {
+                        self.bytes = &[];
+                        return None;
+                    }
+                    let (body, rest) = rest.split_at(link);
+                    self.bytes = rest;
+                    return Some(CisTuple { code: *code, body });
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// CISTPL_MANFID
+pub struct ManfId {
+    /// TPLMID_MANF, the PCMCIA/SDA manufacturer code
+    pub manufacturer: u16,
+    /// TPLMID_CARD, the manufacturer's part number
+    pub card: u16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// CISTPL_FUNCID, the function code is 0x0C for SDIO cards
+pub struct FuncId {
+    pub function: u8,
+    pub sysinit: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// CISTPL_FUNCE
+pub enum FuncE {
+    /// Common CIS (function 0)
+    Common {
+        /// TPLFE_FN0_BLK_SIZE, maximum block size of function 0
+        max_block_size: u16,
+        /// TPLFE_MAX_TRAN_SPEED
+        max_tran_speed: TranSpeed,
+    },
+    /// Function CIS (function 1..=7)
+    Function {
+        /// TPLFE_FUNCTION_INFO, bit 0 wake up support
+        function_info: u8,
+        /// TPLFE_STD_IO_REV
+        std_io_rev: u8,
+        /// TPLFE_CARD_PSN
+        serial_number: u32,
+        /// TPLFE_MAX_BLK_SIZE
+        max_block_size: u16,
+    },
+}
+
+impl FuncE {
+    pub fn max_block_size(&self) -> u16 {
+        match self {
+            FuncE::Common { max_block_size, .. } | FuncE::Function { max_block_size, .. } => *max_block_size,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Maximum transfer rate per data line, coded like TRAN_SPEED of the CSD
+pub struct TranSpeed(pub u8);
+
+impl TranSpeed {
+    /// Transfer rate in kbit/s
+    pub fn kbit_per_s(&self) -> u32 {
+        const VALUE_X10: [u32; 16] = [0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80];
+        const UNIT_KBIT: [u32; 8] = [100, 1_000, 10_000, 100_000, 0, 0, 0, 0];
+        VALUE_X10[((self.0 >> 3) & 0xF) as usize] * UNIT_KBIT[(self.0 & 0b111) as usize] / 10
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// CISTPL_VERS_1, product information strings
+pub struct Vers1<'a> {
+    pub major: u8,
+    pub minor: u8,
+    strings: &'a [u8],
+}
+
+impl<'a> Vers1<'a> {
+    /// Manufacturer, product name and additional info, NUL terminated, the
+    /// list ends with 0xFF
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> {
+        let end = self.strings.iter().position(|b| *b == 0xFF).unwrap_or(self.strings.len());
+        self.strings[..end]
+            .split(|b| *b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+}
+
+/// Copies the CIS chain starting at `address` into `buffer`, `read_byte`
+/// reads one byte of the common register space. Returns the number of
+/// bytes used, decode them with `Cis::new`.
+///
+/// NULL tuples are dropped, a chain that doesn't fit is cut at the last
+/// complete tuple, the chain also ends at the end of the CIS area.
+pub fn read_chain<E>(
+    mut address: u32,
+    buffer: &mut [u8],
+    mut read_byte: impl FnMut(u32) -> Result<u8, E>,
+) -> Result<usize, E> {
+    let mut len = 0;
+    while address <= CIS_AREA_END {
+        let code = read_byte(address)?;
+        if code == CISTPL_NULL {
+            address += 1;
+            continue;
+        }
+        if code == CISTPL_END || len + 2 > buffer.len() {
+            break;
+        }
+        let link = read_byte(address + 1)?;
+        if link == 0xFF || len + 2 + link as usize > buffer.len() {
+            break;
+        }
+        buffer[len] = code;
+        buffer[len + 1] = link;
+        for i in 0..link as usize {
+            buffer[len + 2 + i] = read_byte(address + 2 + i as u32)?;
+        }
+        len += 2 + link as usize;
+        address += 2 + link as u32;
+    }
+
+    if len < buffer.len() {
+        buffer[len] = CISTPL_END;
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Common CIS laid out like the one of a BCM4330 based SDIO WLAN module
+    const COMMON_CIS: &[u8] = &[
+        0x21, 0x02, 0x0C, 0x00, // FUNCID: SDIO
+        0x22, 0x04, 0x00, 0x00, 0x02, 0x32, // FUNCE: 512 bytes, 25 Mbit/s
+        0x20, 0x04, 0xD0, 0x02, 0x30, 0x43, // MANFID: 0x02D0, 0x4330
+        0x00, // NULL
+        0x15, 0x14, 0x01, 0x00, // VERS_1 1.0
+        0x42, 0x72, 0x6F, 0x61, 0x64, 0x63, 0x6F, 0x6D, 0x00, // "Broadcom"
+        0x42, 0x43, 0x4D, 0x34, 0x33, 0x33, 0x30, 0x00, // "BCM4330"
+        0xFF, // end of the strings
+        0x80, 0x03, 0x02, 0x30, 0x43, // vendor
+        0x81, 0x01, 0x07, // vendor
+        0xFF, // END
+        0x20, 0x04, 0x01, 0x02, 0x03, 0x04, // behind END
+    ];
+
+    /// Function 1 CIS in the same layout
+    const FUNCTION_CIS: &[u8] = &[
+        0x21, 0x02, 0x0C, 0x00, // FUNCID: SDIO
+        0x22, 0x2A, // FUNCE, function extension
+        0x01, 0x01, 0x00, // TPLFE_TYPE, FUNCTION_INFO (wake up), STD_IO_REV
+        0x78, 0x56, 0x34, 0x12, // CARD_PSN
+        0x00, 0x00, 0x00, 0x00, 0x00, // CSA_SIZE, CSA_PROPERTY
+        0x00, 0x02, // MAX_BLK_SIZE
+        0x00, 0x80, 0xFF, 0x00, // OCR
+        0x08, 0x0A, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, // power
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0xFF, // END
+    ];
+
+    #[test]
+    fn common_cis() {
+        let cis = Cis::new(COMMON_CIS);
+        assert_eq!(
+            cis.manfid(),
+            Some(ManfId {
+                manufacturer: 0x02D0,
+                card: 0x4330
+            })
+        );
+        assert_eq!(cis.funcid(), Some(FuncId { function: 0x0C, sysinit: 0 }));
+
+        let funce = cis.funce().unwrap();
+        assert_eq!(
+            funce,
+            FuncE::Common {
+                max_block_size: 512,
+                max_tran_speed: TranSpeed(0x32)
+            }
+        );
+        assert_eq!(funce.max_block_size(), 512);
+
+        let vers_1 = cis.vers_1().unwrap();
+        assert_eq!((vers_1.major, vers_1.minor), (1, 0));
+        let mut strings = vers_1.strings();
+        assert_eq!(strings.next(), Some("Broadcom"));
+        assert_eq!(strings.next(), Some("BCM4330"));
+        assert_eq!(strings.next(), None);
+    }
+
+    #[test]
+    fn function_cis() {
+        let cis = Cis::new(FUNCTION_CIS);
+        assert_eq!(
+            cis.funce(),
+            Some(FuncE::Function {
+                function_info: 0x01,
+                std_io_rev: 0x00,
+                serial_number: 0x1234_5678,
+                max_block_size: 512,
+            })
+        );
+        assert_eq!(cis.manfid(), None);
+        assert_eq!(cis.vers_1(), None);
+    }
+
+    #[test]
+    fn vendor_tuples() {
+        let cis = Cis::new(COMMON_CIS);
+        let mut vendor = cis.vendor_tuples();
+        assert_eq!(
+            vendor.next(),
+            Some(CisTuple {
+                code: 0x80,
+                body: &[0x02, 0x30, 0x43]
+            })
+        );
+        assert_eq!(vendor.next(), Some(CisTuple { code: 0x81, body: &[0x07] }));
+        assert_eq!(vendor.next(), None);
+    }
+
+    #[test]
+    fn stops_at_end() {
+        let codes: Vec<u8> = Cis::new(COMMON_CIS).map(|t| t.code).collect();
+        assert_eq!(
+            codes,
+            [CISTPL_FUNCID, CISTPL_FUNCE, CISTPL_MANFID, CISTPL_VERS_1, 0x80, 0x81]
+        );
+    }
+
+    #[test]
+    fn skips_null_tuples() {
+        let bytes = [0x00, 0x00, 0x21, 0x02, 0x0C, 0x00, 0x00, 0x20, 0x04, 0xD0, 0x02, 0x30, 0x43, 0xFF];
+        let codes: Vec<u8> = Cis::new(&bytes).map(|t| t.code).collect();
+        assert_eq!(codes, [CISTPL_FUNCID, CISTPL_MANFID]);
+        assert!(Cis::new(&bytes).manfid().is_some());
+    }
+
+    #[test]
+    fn end_of_chain_link_and_cut_off_tuple() {
+        // link 0xFF ends the chain
+        assert_eq!(Cis::new(&[0x21, 0x02, 0x0C, 0x00, 0x20, 0xFF]).count(), 1);
+        // the body of MANFID is cut off
+        assert_eq!(Cis::new(&[0x21, 0x02, 0x0C, 0x00, 0x20, 0x04, 0xD0]).count(), 1);
+        // missing END
+        assert_eq!(Cis::new(&[0x21, 0x02, 0x0C, 0x00]).count(), 1);
+    }
+
+    #[test]
+    fn short_bodies_are_not_decoded() {
+        let cis = Cis::new(&[0x20, 0x02, 0xD0, 0x02, 0x22, 0x02, 0x01, 0x01, 0xFF]);
+        assert_eq!(cis.count(), 2);
+        assert_eq!(Cis::new(&[0x20, 0x02, 0xD0, 0x02, 0xFF]).manfid(), None);
+        assert_eq!(Cis::new(&[0x22, 0x02, 0x01, 0x01, 0xFF]).funce(), None);
+    }
+
+    #[test]
+    fn tran_speed() {
+        assert_eq!(TranSpeed(0x32).kbit_per_s(), 25_000);
+        assert_eq!(TranSpeed(0x5A).kbit_per_s(), 50_000);
+        assert_eq!(TranSpeed(0x00).kbit_per_s(), 0);
+    }
+
+    /// Common register space with the common CIS at 0x1000
+    fn register_space() -> Vec<u8> {
+        let mut space = vec![0; 0x2000];
+        space[0x1000..0x1000 + COMMON_CIS.len()].copy_from_slice(COMMON_CIS);
+        space
+    }
+
+    #[test]
+    fn reads_the_chain() {
+        let space = register_space();
+        let mut buffer = [0u8; 64];
+        let len = read_chain(0x1000, &mut buffer, |address| Ok::<_, ()>(space[address as usize])).unwrap();
+        // the NULL tuple is dropped
+        assert_eq!(len, 46);
+        assert_eq!(buffer[len], CISTPL_END);
+        let cis = Cis::new(&buffer[..len]);
+        assert_eq!(cis.manfid(), Cis::new(COMMON_CIS).manfid());
+        assert_eq!(cis.vendor_tuples().count(), 2);
+    }
+
+    #[test]
+    fn chain_is_cut_at_the_last_complete_tuple() {
+        let space = register_space();
+        let mut buffer = [0u8; 12];
+        let len = read_chain(0x1000, &mut buffer, |address| Ok::<_, ()>(space[address as usize])).unwrap();
+        assert_eq!(len, 10);
+        assert_eq!(buffer[10], CISTPL_END);
+        assert_eq!(Cis::new(&buffer[..len]).count(), 2);
+    }
+
+    #[test]
+    fn chain_of_nulls_ends_at_the_cis_area() {
+        let mut reads = 0;
+        let mut buffer = [0u8; 16];
+        let len = read_chain(CIS_AREA_END - 15, &mut buffer, |_| {
+            reads += 1;
+            Ok::<_, ()>(CISTPL_NULL)
+        })
+        .unwrap();
+        assert_eq!((len, reads), (0, 16));
+    }
+
+    #[test]
+    fn read_errors_are_passed_on() {
+        let mut buffer = [0u8; 16];
+        assert_eq!(read_chain(0x1000, &mut buffer, |_| Err("timeout")), Err("timeout"));
+    }
+}
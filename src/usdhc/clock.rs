@@ -0,0 +1,47 @@
+//! # Microsecond clock
+//!
+//! Time base of the timeouts of the command engine (`engine.rs`), taken
+//! from the cycle counter of the core (DWT CYCCNT). The counter wraps every
+//! 7 s at 600 MHz, it's extended to 64 bit on every read, the waits read it
+//! each time they are polled.
+
+use core::cell::Cell;
+use cortex_m::{
+    interrupt::Mutex,
+    peripheral::{DCB, DWT},
+};
+
+use super::ARM_CLOCK_HZ;
+
+/// DEMCR[TRCENA], enables the DWT
+const DEMCR_TRCENA: u32 = 1 << 24;
+/// DWT_CTRL[CYCCNTENA]
+const CTRL_CYCCNTENA: u32 = 1;
+/// Key of the DWT lock access register
+const LAR_KEY: u32 = 0xC5AC_CE55;
+
+/// CYCCNT at the previous read and the cycles counted up to it
+static CYCLES: Mutex<Cell<(u32, u64)>> = Mutex::new(Cell::new((0, 0)));
+
+/// Starts the cycle counter, if it isn't running yet
+pub fn enable() {
+    // Safety: only the enable bits are set, the counter itself is read-only
+    // for the rest of the driver
+    unsafe {
+        (*DCB::PTR).demcr.modify(|demcr| demcr | DEMCR_TRCENA);
+        (*DWT::PTR).lar.write(LAR_KEY);
+        (*DWT::PTR).ctrl.modify(|ctrl| ctrl | CTRL_CYCCNTENA);
+    }
+}
+
+/// Microseconds since the counter was started, wraps after 71 minutes
+pub fn now_us() -> u32 {
+    cortex_m::interrupt::free(|cs| {
+        let cycles = CYCLES.borrow(cs);
+        let (last, total) = cycles.get();
+        let now = DWT::cycle_count();
+        let total = total + now.wrapping_sub(last) as u64;
+        cycles.set((now, total));
+        (total / (ARM_CLOCK_HZ / 1_000_000) as u64) as u32
+    })
+}
@@ -3,6 +3,13 @@ pub trait SdCommand {
     const RESPONSE: Response;
     const TYPE: CommandType;
     const APP_CMD: bool = false;
+    /// direction of the data phase, sets CMD_XFR_TYP[DPSEL] if present
+    const DATA: DataDirection = DataDirection::None;
+    /// abort commands (CMD12, CMD52 abort) set CMD_XFR_TYP[CMDTYP] to `11`
+    const ABORT: bool = false;
+    /// R1b commands with a long busy phase (erase), the caller waits for the
+    /// end of the busy signal with its own timeout
+    const LONG_BUSY: bool = false;
 
     fn mk_args(&self) -> u32;
     #[inline]
@@ -10,11 +17,17 @@ pub trait SdCommand {
         log::debug!("mk xfer");
 
         let resp_flags: u32 = Self::RESPONSE.into();
+        let data_flags: u32 = if Self::DATA != DataDirection::None {
+            1 << 21
+        } else {
+            0
+        };
+        let type_flags: u32 = if Self::ABORT { 0b11 << 22 } else { 0 };
 
         log::debug!("mk xfer {:32b}", resp_flags);
         log::debug!("id {:32b}", self.cmd_id() << 24);
         log::debug!("res {:32b}", resp_flags | (self.cmd_id() << 24));
-        resp_flags | (self.cmd_id() << 24)
+        resp_flags | data_flags | type_flags | (self.cmd_id() << 24)
     }
     #[inline]
     fn req_app_cmd(&self) -> bool {
@@ -41,6 +54,7 @@ pub enum CommandType {
     AddressedCommand,
     AddressedDataTransferCommand,
 }
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Response {
     None,
     R1,
@@ -53,6 +67,17 @@ pub enum Response {
     R6,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Direction of the data phase of an adtc command
+pub enum DataDirection {
+    /// no data on the DAT lines
+    None,
+    /// card to host
+    Read,
+    /// host to card
+    Write,
+}
+
 impl Into<u32> for Response {
     #[inline]
     fn into(self) -> u32 {
@@ -344,7 +369,11 @@ pub enum AcCommand {
     /// any I/O function.
     ///
     /// ## Arguments:
-    /// [31:0] stuff bits
+    /// [31] R/W flag
+    /// [30:28] function number
+    /// [27] RAW flag
+    /// [25:9] register address
+    /// [7:0] write data
     ///
     /// response type: R5
     IoRwDirect = 52,
@@ -354,7 +383,12 @@ pub enum AcCommand {
     /// reading or writing of a large number of I/O registers.
     ///
     /// ## Arguments:
-    /// [31:0] stuff bits
+    /// [31] R/W flag
+    /// [30:28] function number
+    /// [27] block mode
+    /// [26] OP code
+    /// [25:9] register address
+    /// [8:0] byte/block count
     ///
     /// response type: R5
     IoRwExtended = 53,
@@ -767,18 +801,24 @@ impl SdCommand for SetSendRelativeAddr {
 /// Checks switch ability (mode 0) and switch card function (mode 1).
 /// Refer to "SD Physical Specification V1.1" for more details.
 ///
+/// The card answers with the 512 bit switch function status.
+///
 /// ## Arguments:
 /// [31] Mode:  0: Check function 1: Switch function
-/// [30:8] Reserved for function groups 6 ~ 3 (All 0 or 0xFFFF)
-/// [7:4] Function group1 for command system
-/// [3:0] Function group2 for access mode
+/// [30:24] reserved (all 0)
+/// [23:4] Function groups 6 ~ 2 (0xF = no influence)
+/// [3:0] Function group1 for access mode
 ///
 /// response type: R1
-pub struct SwitchFunc(bool, u32, u32);
+pub struct SwitchFunc(u32);
 
 impl SwitchFunc {
-    pub fn new(mode: bool, command_system: u8, access_mode: u8) -> Self {
-        Self(mode, command_system as u32, access_mode as u32)
+    /// Checks (`switch = false`) or selects `function` of function group
+    /// `group` (1..=6), the other groups keep their function
+    pub fn new(switch: bool, group: u8, function: u8) -> Self {
+        let shift = 4 * (group.clamp(1, 6) as u32 - 1);
+        let groups = 0x00FF_FFFF & !(0xF << shift) | ((function as u32) & 0xF) << shift;
+        Self((switch as u32) << 31 | groups)
     }
 }
 
@@ -786,11 +826,10 @@ impl SdCommand for SwitchFunc {
     const CMD: u32 = 6;
     const RESPONSE: Response = Response::R1;
     const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
 
     fn mk_args(&self) -> u32 {
-        (if self.0 { 0x8000_0000 } else { 0x0000_0000 })
-            & ((self.1 & 0b0111) << 4)
-            & (self.2 & 0b0111)
+        self.0
     }
 }
 
@@ -901,7 +940,7 @@ impl SendCsd {
 
 impl SdCommand for SendCsd {
     const CMD: u32 = 9;
-    const RESPONSE: Response = Response::R1;
+    const RESPONSE: Response = Response::R2;
     const TYPE: CommandType = CommandType::AddressedCommand;
 
     fn mk_args(&self) -> u32 {
@@ -955,7 +994,7 @@ impl SendStatus {
 
 impl SdCommand for SendStatus {
     const CMD: u32 = 13;
-    const RESPONSE: Response = Response::R1b;
+    const RESPONSE: Response = Response::R1;
     const TYPE: CommandType = CommandType::AddressedCommand;
 
     fn mk_args(&self) -> u32 {
@@ -979,9 +1018,17 @@ impl SdCommand for SendStatus {
 pub struct SdAppOpCond(u32);
 
 impl SdAppOpCond {
+    /// Switching to 1.8V request (S18R), answered with S18A in the OCR
+    pub const S18R: u32 = 1 << 24;
+
     pub fn new(orc: u32) -> Self {
         Self(orc)
     }
+
+    /// Requests 1.8V signaling, see `USdhc::switch_to_1v8`
+    pub fn with_s18r(self) -> Self {
+        Self(self.0 | Self::S18R)
+    }
 }
 
 impl SdCommand for SdAppOpCond {
@@ -1022,3 +1069,950 @@ impl SdCommand for AppCmd {
         self.0 << 16
     }
 }
+
+/// ## CMD12
+///
+/// Forces the card to stop transmission.
+///
+/// Sent as abort command (CMD_XFR_TYP[CMDTYP] = `11`) so the uSDHC
+/// releases the data lines even while a transfer is still active.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1b
+pub struct StopTransmission(());
+
+impl StopTransmission {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for StopTransmission {
+    const CMD: u32 = 12;
+    const RESPONSE: Response = Response::R1b;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+    const ABORT: bool = true;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD16
+///
+/// Sets the block length (in bytes) for all following block commands
+/// (read and write). Default block length is specified in the CSD.
+///
+/// ## Arguments:
+/// [31:0] block length
+///
+/// response type: R1
+pub struct SetBlocklen(u32);
+
+impl SetBlocklen {
+    pub fn new(length: u32) -> Self {
+        Self(length)
+    }
+}
+
+impl SdCommand for SetBlocklen {
+    const CMD: u32 = 16;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD17
+///
+/// Reads a block of the size selected by the SET_BLOCKLEN command.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1
+pub struct ReadSingleBlock(u32);
+
+impl ReadSingleBlock {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for ReadSingleBlock {
+    const CMD: u32 = 17;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD18
+///
+/// Continuously transfers data blocks from card to host until
+/// interrupted by a stop command.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1
+pub struct ReadMultipleBlock(u32);
+
+impl ReadMultipleBlock {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for ReadMultipleBlock {
+    const CMD: u32 = 18;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD23
+///
+/// Specifies the block count for the following multiple block read or
+/// write command. Only supported if SCR[CMD_SUPPORT] says so.
+///
+/// ## Arguments:
+/// [31:0] block count
+///
+/// response type: R1
+pub struct SetBlockCount(u32);
+
+impl SetBlockCount {
+    pub fn new(count: u32) -> Self {
+        Self(count)
+    }
+}
+
+impl SdCommand for SetBlockCount {
+    const CMD: u32 = 23;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## ACMD51
+///
+/// **Type:** adtc
+///
+/// Reads the SD Configuration Register (SCR).
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct SendScr(());
+
+impl SendScr {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for SendScr {
+    const CMD: u32 = 51;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const APP_CMD: bool = true;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD24
+///
+/// Writes a block of the size selected by the SET_BLOCKLEN command.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1
+pub struct WriteBlock(u32);
+
+impl WriteBlock {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for WriteBlock {
+    const CMD: u32 = 24;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Write;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD25
+///
+/// Continuously writes blocks of data until a STOP_TRANSMISSION follows.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1
+pub struct WriteMultipleBlock(u32);
+
+impl WriteMultipleBlock {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for WriteMultipleBlock {
+    const CMD: u32 = 25;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Write;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## ACMD22
+///
+/// **Type:** adtc
+///
+/// Send the number of the written sectors (without errors). Responds
+/// with 32-bit plus the CRC data block.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct SendNumWrSectors(());
+
+impl SendNumWrSectors {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for SendNumWrSectors {
+    const CMD: u32 = 22;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const APP_CMD: bool = true;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## ACMD23
+///
+/// **Type:** ac
+///
+/// Set the number of write blocks to be pre-erased before writing
+/// (to be used for fast Multiple Block WR command).
+/// "1" = default(one write block).
+///
+/// ## Arguments:
+/// [31:23] stuff bits
+/// [22:0] Number of blocks
+///
+/// response type: R1
+pub struct SetWrBlkEraseCount(u32);
+
+impl SetWrBlkEraseCount {
+    pub fn new(count: u32) -> Self {
+        Self(count & 0x7F_FFFF)
+    }
+}
+
+impl SdCommand for SetWrBlkEraseCount {
+    const CMD: u32 = 23;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+    const APP_CMD: bool = true;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// CMD53 argument
+///
+/// [31] R/W flag, [30:28] function number, [27] block mode, [26] OP code
+/// (incrementing address), [25:9] register address, [8:0] byte/block count
+fn io_rw_extended_args(write: bool, function: u8, address: u32, block_mode: bool, increment: bool, count: u16) -> u32 {
+    (write as u32) << 31
+        | ((function as u32) & 0b111) << 28
+        | (block_mode as u32) << 27
+        | (increment as u32) << 26
+        | (address & 0x1_FFFF) << 9
+        | (count as u32) & 0x1FF
+}
+
+/// ## CMD53 (read)
+///
+/// Reads `count` bytes (byte mode, 0 = 512 bytes) or `count` blocks of the
+/// function block size (block mode) from the I/O register space.
+///
+/// ## Arguments:
+/// [31] R/W flag = 0
+/// [30:28] function number
+/// [27] block mode
+/// [26] OP code, 1 = incrementing address
+/// [25:9] register address
+/// [8:0] byte/block count
+///
+/// response type: R5
+pub struct IoReadExtended(u32);
+
+impl IoReadExtended {
+    pub fn new(function: u8, address: u32, block_mode: bool, increment: bool, count: u16) -> Self {
+        Self(io_rw_extended_args(false, function, address, block_mode, increment, count))
+    }
+}
+
+impl SdCommand for IoReadExtended {
+    const CMD: u32 = 53;
+    const RESPONSE: Response = Response::R5;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD53 (write)
+///
+/// Writes `count` bytes (byte mode, 0 = 512 bytes) or `count` blocks of the
+/// function block size (block mode) into the I/O register space.
+///
+/// ## Arguments:
+/// [31] R/W flag = 1
+/// [30:28] function number
+/// [27] block mode
+/// [26] OP code, 1 = incrementing address
+/// [25:9] register address
+/// [8:0] byte/block count
+///
+/// response type: R5
+pub struct IoWriteExtended(u32);
+
+impl IoWriteExtended {
+    pub fn new(function: u8, address: u32, block_mode: bool, increment: bool, count: u16) -> Self {
+        Self(io_rw_extended_args(true, function, address, block_mode, increment, count))
+    }
+}
+
+impl SdCommand for IoWriteExtended {
+    const CMD: u32 = 53;
+    const RESPONSE: Response = Response::R5;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Write;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD52
+///
+/// Reads or writes a single register of the I/O register space. With the
+/// RAW flag the register is read back after the write.
+///
+/// ## Arguments:
+/// [31] R/W flag
+/// [30:28] function number
+/// [27] RAW flag
+/// [26] stuff
+/// [25:9] register address
+/// [8] stuff
+/// [7:0] write data
+///
+/// response type: R5
+pub struct IoRwDirect(u32);
+
+impl IoRwDirect {
+    pub fn read(function: u8, address: u32) -> Self {
+        Self(((function as u32) & 0b111) << 28 | (address & 0x1_FFFF) << 9)
+    }
+
+    pub fn write(function: u8, address: u32, value: u8, read_after_write: bool) -> Self {
+        Self(
+            1 << 31
+                | ((function as u32) & 0b111) << 28
+                | (read_after_write as u32) << 27
+                | (address & 0x1_FFFF) << 9
+                | value as u32,
+        )
+    }
+}
+
+impl SdCommand for IoRwDirect {
+    const CMD: u32 = 52;
+    const RESPONSE: Response = Response::R5;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD52 (I/O abort)
+///
+/// Writes the function number into ASx of the CCCR I/O Abort register
+/// (0x06) to stop a CMD53 transfer, sent as abort command.
+///
+/// ## Arguments:
+/// see CMD52
+///
+/// response type: R5
+pub struct IoAbort(u32);
+
+impl IoAbort {
+    pub fn new(function: u8) -> Self {
+        Self(IoRwDirect::write(0, 0x06, function & 0b111, false).0)
+    }
+}
+
+impl SdCommand for IoAbort {
+    const CMD: u32 = 52;
+    const RESPONSE: Response = Response::R5;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+    const ABORT: bool = true;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD27
+///
+/// Programming of the programmable bits of the CSD, the 16 byte CSD
+/// follows as data block.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct ProgramCsd(());
+
+impl ProgramCsd {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for ProgramCsd {
+    const CMD: u32 = 27;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Write;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD28
+///
+/// Sets the write protection bit of the addressed group.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1b
+pub struct SetWriteProt(u32);
+
+impl SetWriteProt {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for SetWriteProt {
+    const CMD: u32 = 28;
+    const RESPONSE: Response = Response::R1b;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD29
+///
+/// Clears the write protection bit of the addressed group.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1b
+pub struct ClrWriteProt(u32);
+
+impl ClrWriteProt {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for ClrWriteProt {
+    const CMD: u32 = 29;
+    const RESPONSE: Response = Response::R1b;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD30
+///
+/// Sends the write protection bits of 32 groups starting at the addressed
+/// group as a 4 byte data block.
+///
+/// ## Arguments:
+/// [31:0] write protect data address
+///
+/// response type: R1
+pub struct SendWriteProt(u32);
+
+impl SendWriteProt {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for SendWriteProt {
+    const CMD: u32 = 30;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD42
+///
+/// Sets or clears the password, locks or unlocks the card or forces an
+/// erase. The lock card data structure follows as data block, its size has
+/// to be set with SET_BLOCKLEN before.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct LockUnlock(());
+
+impl LockUnlock {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for LockUnlock {
+    const CMD: u32 = 42;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Write;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD56 (read)
+///
+/// Reads a general purpose / vendor specific data block of the size set
+/// with SET_BLOCKLEN.
+///
+/// ## Arguments:
+/// [31:1] vendor specific argument
+/// [0]: RD/WR = 1
+///
+/// response type: R1
+pub struct GenCmdRead(u32);
+
+impl GenCmdRead {
+    pub fn new(argument: u32) -> Self {
+        Self(argument | 1)
+    }
+}
+
+impl SdCommand for GenCmdRead {
+    const CMD: u32 = 56;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD56 (write)
+///
+/// Writes a general purpose / vendor specific data block of the size set
+/// with SET_BLOCKLEN.
+///
+/// ## Arguments:
+/// [31:1] vendor specific argument
+/// [0]: RD/WR = 0
+///
+/// response type: R1
+pub struct GenCmdWrite(u32);
+
+impl GenCmdWrite {
+    pub fn new(argument: u32) -> Self {
+        Self(argument & !1)
+    }
+}
+
+impl SdCommand for GenCmdWrite {
+    const CMD: u32 = 56;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Write;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy)]
+/// The access bits for the EXT_CSD access modes are listed in the following enumeration.
+pub enum ExtCsd {
+    /// The command set is changed according to the Cmd Set field of the argument.
+    CommandSet = 0b00,
+    /// The bits in the pointed byte are set, according to the bits set to 1 in the Value field.
+    SetBits = 0b01,
+    /// The bits in the pointed byte are cleared, according to the bits set to 1 in the Value field.
+    ClearBits = 0b10,
+    /// The Value field is written into the pointed byte.
+    WriteByte = 0b11,
+}
+
+/// ## CMD6 (eMMC)
+///
+/// SWITCH, modifies a byte of the EXT_CSD register.
+///
+/// ## Arguments:
+/// [25:24] Access
+/// [23:16] Index
+/// [15:8] Value
+/// [2:0] Cmd Set
+///
+/// response type: R1b
+pub struct Switch(u32);
+
+impl Switch {
+    pub fn new(access: ExtCsd, index: u8, value: u8) -> Self {
+        Self((access as u32) << 24 | (index as u32) << 16 | (value as u32) << 8)
+    }
+}
+
+impl SdCommand for Switch {
+    const CMD: u32 = 6;
+    const RESPONSE: Response = Response::R1b;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD19 (eMMC)
+///
+/// BUSTEST_W, the host sends the bus test pattern.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct BusTestWrite(());
+
+impl BusTestWrite {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for BusTestWrite {
+    const CMD: u32 = 19;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Write;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD14 (eMMC)
+///
+/// BUSTEST_R, the card sends the inverted pattern of the last BUSTEST_W.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct BusTestRead(());
+
+impl BusTestRead {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for BusTestRead {
+    const CMD: u32 = 14;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD11 (SD)
+///
+/// VOLTAGE_SWITCH, switches the signaling to 1.8V. Only valid after ACMD41
+/// reported S18A.
+///
+/// ## Arguments:
+/// [31:0] reserved bits (all 0)
+///
+/// response type: R1
+pub struct VoltageSwitch(());
+
+impl VoltageSwitch {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for VoltageSwitch {
+    const CMD: u32 = 11;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD19 (SD)
+///
+/// 64 bytes tuning pattern is sent for SDR50 and SDR104.
+///
+/// ## Arguments:
+/// [31:0] reserved bits(all 0)
+///
+/// response type: R1
+pub struct SendTuningBlock64(());
+
+impl SendTuningBlock64 {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for SendTuningBlock64 {
+    const CMD: u32 = 19;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD21 (eMMC)
+///
+/// 128 clocks of tuning pattern (64 byte in 4 bit mode or 128 byte in 8 bit mode)
+/// is sent for HS200 optimal sampling point detection.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct SendTuningBlock128(());
+
+impl SendTuningBlock128 {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for SendTuningBlock128 {
+    const CMD: u32 = 21;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD8 (eMMC)
+///
+/// SEND_EXT_CSD, the device sends its EXT_CSD register as a block of data,
+/// with a block size of 512 bytes.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct SendExtCsdBlock(());
+
+impl SendExtCsdBlock {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for SendExtCsdBlock {
+    const CMD: u32 = 8;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
+
+/// ## CMD32
+///
+/// Sets the address of the first write block to be erased.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1
+pub struct TagSectorStart(u32);
+
+impl TagSectorStart {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for TagSectorStart {
+    const CMD: u32 = 32;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD33
+///
+/// Sets the address of the last write block of the continuous range to be
+/// erased.
+///
+/// ## Arguments:
+/// [31:0] data address
+///
+/// response type: R1
+pub struct TagSectorEnd(u32);
+
+impl TagSectorEnd {
+    pub fn new(address: u32) -> Self {
+        Self(address)
+    }
+}
+
+impl SdCommand for TagSectorEnd {
+    const CMD: u32 = 33;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## CMD38
+///
+/// Erases all previously selected write blocks.
+///
+/// ## Arguments:
+/// [31:0] erase function: 0 = erase, 1 = discard, 2 = FULE
+///
+/// response type: R1b
+pub struct Erase(u32);
+
+impl Erase {
+    pub fn new(function: u32) -> Self {
+        Self(function)
+    }
+}
+
+impl SdCommand for Erase {
+    const CMD: u32 = 38;
+    const RESPONSE: Response = Response::R1b;
+    const TYPE: CommandType = CommandType::AddressedCommand;
+    const LONG_BUSY: bool = true;
+
+    fn mk_args(&self) -> u32 {
+        self.0
+    }
+}
+
+/// ## ACMD13
+///
+/// **Type:** adtc
+///
+/// Send the SD Memory Card status, a 512 bit data block.
+///
+/// ## Arguments:
+/// [31:0] stuff bits
+///
+/// response type: R1
+pub struct SendSdStatus(());
+
+impl SendSdStatus {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl SdCommand for SendSdStatus {
+    const CMD: u32 = 13;
+    const RESPONSE: Response = Response::R1;
+    const TYPE: CommandType = CommandType::AddressedDataTransferCommand;
+    const APP_CMD: bool = true;
+    const DATA: DataDirection = DataDirection::Read;
+
+    fn mk_args(&self) -> u32 {
+        0
+    }
+}
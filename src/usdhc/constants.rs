@@ -38,8 +38,32 @@ pub enum CardMode {
     SdSdioUhsI,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// Number of data lines, PROT_CTRL[DTW]
+pub enum BusWidth {
+    One,
+    Four,
+    Eight,
+}
+
+impl BusWidth {
+    /// Value of PROT_CTRL[DTW]
+    pub fn dtw(&self) -> u32 {
+        match self {
+            BusWidth::One => 0b00,
+            BusWidth::Four => 0b01,
+            BusWidth::Eight => 0b10,
+        }
+    }
+}
+
 pub const SD_MAX_INIT_RATE_HZ: u32 = 400_000;
+pub const SD_MAX_SD_DEFAULT_SPEED_RATE_HZ: u32 = 25_000_000;
+pub const SD_MAX_SD_HIGH_SPEED_RATE_HZ: u32 = 50_000_000;
 pub const SD_MAX_MMC_FULL_SPEED_RATE_HZ: u32 = 26_000_000;
 pub const SD_MAX_MMC_HIGH_SPEED_RATE_HZ: u32 = 52_000_000;
 pub const SD_MAX_MMC_HS200_RATE_HZ: u32 = 200_000_000;
 pub const SD_MAX_MMC_DDR_RATE_HZ: u32 = 52_000_000;
+
+/// Core clock set up by the teensy4-bsp runtime, used to time busy waits
+pub const ARM_CLOCK_HZ: u32 = 600_000_000;
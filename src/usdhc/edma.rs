@@ -0,0 +1,137 @@
+//! # External eDMA transfers
+//!
+//! With MIX_CTRL[DMAEN] cleared, the uSDHC asserts a DMA request whenever
+//! RD_WML words can be read or WR_WML words can be written (the same
+//! condition that sets BRR/BWR). An eDMA channel then moves one watermark
+//! per minor loop between DATA_BUFF_ACC_PORT and the caller's buffer, so
+//! the buffer can live in any memory region the eDMA can reach.
+//!
+//! The request source number depends on the chip's DMAMUX table and is
+//! passed in by the user. Without a request source the minor loops are
+//! started by software on every BRR/BWR, which still saves the copy loop.
+//! The end of such a minor loop is awaited, the task yields to the executor
+//! until the channel is idle.
+
+use core::task::Poll;
+use teensy4_bsp::hal::dma;
+
+use super::Error;
+
+/// Polls of a software triggered minor loop before it's given up, one
+/// watermark is moved within a few polls
+const MINOR_LOOP_POLLS: u32 = 10_000;
+
+/// eDMA channel reserved for the uSDHC data port
+pub struct ExternalDma {
+    channel: dma::Channel,
+    request: Option<u32>,
+}
+
+impl ExternalDma {
+    /// `request` is the DMAMUX source of the uSDHC DMA request line,
+    /// `None` triggers the channel by software
+    pub fn new(channel: dma::Channel, request: Option<u32>) -> Self {
+        Self { channel, request }
+    }
+
+    /// Releases the eDMA channel
+    pub fn release(self) -> dma::Channel {
+        self.channel
+    }
+
+    pub fn is_hardware_triggered(&self) -> bool {
+        self.request.is_some()
+    }
+
+    /// Number of major loop iterations for `len` bytes with a watermark of
+    /// `wml` words, `None` if the data doesn't split into whole watermarks
+    pub fn iterations(len: usize, wml: usize) -> Option<u16> {
+        let minor = wml * 4;
+        if minor == 0 || len % minor != 0 || len / minor > u16::MAX as usize {
+            None
+        } else {
+            Some((len / minor) as u16)
+        }
+    }
+
+    /// Programs the channel to move `len` bytes from the data port into `buffer`
+    ///
+    /// # Safety
+    ///
+    /// `buffer` has to stay valid until the transfer completed or was stopped.
+    pub unsafe fn prepare_read(&mut self, port: *const u32, buffer: *mut u32, len: usize, wml: usize) {
+        self.prepare(wml, len);
+        self.channel.set_source_transfer(&dma::Transfer::hardware(port));
+        self.channel
+            .set_destination_transfer(&dma::Transfer::buffer_linear(buffer as *const u32, len / 4));
+    }
+
+    /// Programs the channel to move `len` bytes from `buffer` into the data port
+    ///
+    /// # Safety
+    ///
+    /// `buffer` has to stay valid until the transfer completed or was stopped.
+    pub unsafe fn prepare_write(&mut self, port: *const u32, buffer: *const u32, len: usize, wml: usize) {
+        self.prepare(wml, len);
+        self.channel
+            .set_source_transfer(&dma::Transfer::buffer_linear(buffer, len / 4));
+        self.channel.set_destination_transfer(&dma::Transfer::hardware(port));
+    }
+
+    fn prepare(&mut self, wml: usize, len: usize) {
+        self.channel.set_enable(false);
+        self.channel.clear_complete();
+        self.channel.clear_error();
+        self.channel.set_minor_loop_elements::<u32>(wml);
+        self.channel
+            .set_transfer_iterations(Self::iterations(len, wml).unwrap_or(0));
+        self.channel.set_disable_on_completion(true);
+        self.channel.set_trigger_from_hardware(self.request);
+    }
+
+    /// Enables the hardware request, only needed for hardware triggered channels
+    pub fn enable(&mut self) {
+        if self.request.is_some() {
+            self.channel.set_enable(true);
+        }
+    }
+
+    /// Starts one minor loop (one watermark), for software triggered
+    /// channels. The end is awaited with `wait_minor_loop`.
+    pub fn trigger(&mut self) {
+        self.channel.start();
+    }
+
+    /// Waits until the minor loop started by `trigger` is done, fails with
+    /// `Error::Timeout` if the channel stays active
+    pub async fn wait_minor_loop(&mut self) -> Result<(), Error> {
+        let mut polls = 0;
+        core::future::poll_fn(|cx| {
+            if !self.channel.is_active() {
+                Poll::Ready(Ok(()))
+            } else if polls == MINOR_LOOP_POLLS {
+                Poll::Ready(Err(Error::Timeout))
+            } else {
+                polls += 1;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// `Ok(true)` once the major loop completed
+    pub fn poll(&mut self) -> Result<bool, Error> {
+        if self.channel.is_error() {
+            self.channel.clear_error();
+            return Err(Error::Dma);
+        }
+        Ok(self.channel.is_complete())
+    }
+
+    /// Stops the channel after an error or a finished transfer
+    pub fn stop(&mut self) {
+        self.channel.set_enable(false);
+        self.channel.clear_complete();
+    }
+}
@@ -0,0 +1,231 @@
+//! # Command engine
+//!
+//! Sends commands and waits for the uSDHC through a `Registers` backend,
+//! see `regs.rs`. Every wait is a future: INT_STATUS is awaited through the
+//! backend (the ISR with interrupts enabled), the PRES_STATE bits have no
+//! interrupt and are polled. A poll never blocks, the task stays runnable
+//! and yields to the other tasks of the executor in between.
+//!
+//! None of the waits is unbounded. Command and data phases end with CTOE
+//! or DTOE at the latest, the PRES_STATE waits have a timeout measured with
+//! the clock of the backend and the CMD13 polling a retry limit, they fail
+//! with `Error::Timeout`.
+
+use core::task::Poll;
+
+use super::{
+    commands::{DataDirection, Response, SdCommand, SendStatus},
+    error::COMMAND_ERRORS,
+    registers::{CardStatus, CurrentState},
+    regs::{int_status, pres_state, Register, Registers},
+    Error,
+};
+
+/// Time the previous command may keep CMD inhibited
+pub const COMMAND_INHIBIT_TIMEOUT_US: u32 = 10_000;
+/// Time the previous transfer may keep DATA inhibited
+pub const DATA_INHIBIT_TIMEOUT_US: u32 = 100_000;
+/// Busy time of R1b commands and of programming after a write (SDXC
+/// allows 500 ms for a write block)
+pub const BUSY_TIMEOUT_US: u32 = 1_000_000;
+/// CMD13 sent before the card is given up on the way back to transfer
+pub const TRANSFER_STATE_POLLS: u32 = 1000;
+
+/// Waits while `busy` is true for PRES_STATE, fails with `Error::Timeout`
+/// after `timeout_us`
+pub async fn wait_pres_state<R: Registers>(regs: &mut R, busy: fn(u32) -> bool, timeout_us: u32) -> Result<(), Error> {
+    let start_us = regs.now_us();
+    core::future::poll_fn(|cx| {
+        if !busy(regs.read(Register::PresState)) {
+            Poll::Ready(Ok(()))
+        } else if regs.now_us().wrapping_sub(start_us) >= timeout_us {
+            Poll::Ready(Err(Error::Timeout))
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Waits until one of the `done` or `errors` bits is set in INT_STATUS.
+///
+/// The error bits are cleared and mapped into an `Error`, the `done` bits
+/// are left for the caller.
+pub async fn wait_int_status<R: Registers>(regs: &mut R, done: u32, errors: u32) -> Result<u32, Error> {
+    let status = core::future::poll_fn(|cx| regs.poll_int_status(done | errors, cx)).await;
+
+    if status & errors != 0 {
+        regs.write(Register::IntStatus, status & errors);
+        return match Error::from_int_status(status & errors) {
+            Some(Error::AutoCmd12(_)) => Err(Error::AutoCmd12(regs.read(Register::AutoCmd12ErrStatus))),
+            Some(err) => Err(err),
+            None => Err(Error::Unsupported),
+        };
+    }
+    Ok(status)
+}
+
+/// Sends a single command and waits for the response, returns CMD_RSP0.
+///
+/// Waits for the command (and data) inhibit of the previous command
+/// before, and for the busy signal of R1b commands after the response.
+pub async fn issue<R: Registers, C: SdCommand>(regs: &mut R, cmd: &C) -> Result<u32, Error> {
+    wait_pres_state(regs, |state| state & pres_state::CIHB != 0, COMMAND_INHIBIT_TIMEOUT_US).await?;
+    let uses_dat = C::DATA != DataDirection::None || C::RESPONSE == Response::R1b;
+    if uses_dat && !C::ABORT {
+        wait_pres_state(regs, |state| state & pres_state::CDIHB != 0, DATA_INHIBIT_TIMEOUT_US).await?;
+    }
+
+    regs.write(Register::CmdArg, cmd.mk_args());
+    regs.write(Register::CmdXfrTyp, cmd.mk_xfer());
+
+    wait_int_status(regs, int_status::CC, COMMAND_ERRORS).await?;
+    regs.write(Register::IntStatus, int_status::CC);
+
+    if C::RESPONSE == Response::R1b && C::DATA == DataDirection::None && !C::LONG_BUSY {
+        wait_pres_state(regs, |state| state & pres_state::DAT0 == 0, BUSY_TIMEOUT_US).await?;
+    }
+    Ok(regs.read(Register::CmdRsp0))
+}
+
+/// Polls CMD13 until the card is back in the transfer state and ready for
+/// data, returns the last card status
+pub async fn wait_for_transfer_state<R: Registers>(regs: &mut R, rca: u16) -> Result<CardStatus, Error> {
+    for _ in 0..TRANSFER_STATE_POLLS {
+        let status = CardStatus(issue(regs, &SendStatus::new(rca)).await?);
+        if status.current_state() == CurrentState::Tran && status.is_ready_for_data() {
+            return status.check();
+        }
+        if status.0 & CardStatus::ERROR_MASK != 0 {
+            return Err(Error::Card(status));
+        }
+    }
+    log::error!("card did not return to transfer state");
+    Err(Error::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usdhc::{
+        commands::{ExtCsd, Switch},
+        executor::block_on,
+        sim::{Reply, SimUsdhc, CLOCK_STEP_US},
+    };
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Context, Wake, Waker},
+    };
+
+    /// R1 of a card in the transfer state, ready for data
+    const TRAN: u32 = 4 << 9 | 1 << 8;
+    /// R1 of a card in the programming state
+    const PRG: u32 = 7 << 9;
+
+    #[test]
+    fn returns_the_response() {
+        let mut sim = SimUsdhc::new(&[Reply::response(TRAN)]);
+        assert_eq!(block_on(issue(&mut sim, &SendStatus::new(0x1234))), Ok(TRAN));
+        assert_eq!(sim.sent, [(13, 0x1234 << 16)]);
+        assert_eq!(sim.int_status, 0, "CC is cleared");
+    }
+
+    #[test]
+    fn maps_command_errors() {
+        let mut sim = SimUsdhc::new(&[Reply::Error(int_status::CTOE)]);
+        assert_eq!(block_on(issue(&mut sim, &SendStatus::new(1))), Err(Error::CommandTimeout));
+        assert_eq!(sim.int_status, 0, "error bits are cleared");
+    }
+
+    #[test]
+    fn waits_for_the_command_inhibit() {
+        let mut sim = SimUsdhc::new(&[Reply::response(TRAN)]);
+        sim.command_inhibit_until_us = 500;
+        assert_eq!(block_on(issue(&mut sim, &SendStatus::new(1))), Ok(TRAN));
+        assert!(sim.now_us >= 500);
+    }
+
+    #[test]
+    fn stuck_command_inhibit_times_out() {
+        let mut sim = SimUsdhc::new(&[Reply::response(TRAN)]);
+        sim.command_inhibit_until_us = u32::MAX;
+        assert_eq!(block_on(issue(&mut sim, &SendStatus::new(1))), Err(Error::Timeout));
+        assert!(sim.sent.is_empty(), "no command is sent while CMD is inhibited");
+    }
+
+    #[test]
+    fn stuck_data_inhibit_times_out_for_r1b() {
+        let mut sim = SimUsdhc::new(&[Reply::response(TRAN)]);
+        sim.data_inhibit_until_us = u32::MAX;
+        let switch = Switch::new(ExtCsd::WriteByte, 185, 1);
+        assert_eq!(block_on(issue(&mut sim, &switch)), Err(Error::Timeout));
+        assert!(sim.sent.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_end_of_busy() {
+        let mut sim = SimUsdhc::new(&[Reply::Response { response: PRG, busy_us: 2000 }]);
+        let switch = Switch::new(ExtCsd::WriteByte, 185, 1);
+        assert_eq!(block_on(issue(&mut sim, &switch)), Ok(PRG));
+        assert!(sim.now_us >= 2000);
+    }
+
+    #[test]
+    fn stuck_busy_times_out() {
+        let mut sim = SimUsdhc::new(&[Reply::Response { response: PRG, busy_us: u32::MAX }]);
+        let switch = Switch::new(ExtCsd::WriteByte, 185, 1);
+        assert_eq!(block_on(issue(&mut sim, &switch)), Err(Error::Timeout));
+        assert!(sim.now_us >= BUSY_TIMEOUT_US);
+    }
+
+    #[test]
+    fn busy_wait_yields_instead_of_blocking() {
+        struct Flag(AtomicBool);
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let mut sim = SimUsdhc::new(&[]);
+        sim.busy_until_us = u32::MAX;
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let busy = |state: u32| state & pres_state::DAT0 == 0;
+        {
+            let wait = pin!(wait_pres_state(&mut sim, busy, BUSY_TIMEOUT_US));
+            assert!(wait.poll(&mut Context::from_waker(&waker)).is_pending());
+        }
+        assert!(flag.0.load(Ordering::Relaxed), "the task is woken to poll again");
+        assert_eq!(sim.now_us, 2 * CLOCK_STEP_US, "no time passes besides the clock reads");
+    }
+
+    #[test]
+    fn polls_until_transfer_state() {
+        let mut sim = SimUsdhc::new(&[Reply::response(PRG), Reply::response(PRG), Reply::response(TRAN)]);
+        let status = block_on(wait_for_transfer_state(&mut sim, 1)).unwrap();
+        assert_eq!(status.0, TRAN);
+        assert_eq!(sim.sent.len(), 3);
+    }
+
+    #[test]
+    fn transfer_state_polls_are_limited() {
+        let replies = [Reply::response(PRG); TRANSFER_STATE_POLLS as usize + 1];
+        let mut sim = SimUsdhc::new(&replies);
+        assert_eq!(block_on(wait_for_transfer_state(&mut sim, 1)), Err(Error::Timeout));
+        assert_eq!(sim.sent.len(), TRANSFER_STATE_POLLS as usize);
+    }
+
+    #[test]
+    fn card_errors_end_the_polling() {
+        let out_of_range = 1 << 31;
+        let mut sim = SimUsdhc::new(&[Reply::response(PRG | out_of_range)]);
+        assert!(matches!(block_on(wait_for_transfer_state(&mut sim, 1)), Err(Error::Card(_))));
+    }
+}
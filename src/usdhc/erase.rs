@@ -0,0 +1,118 @@
+//! # Erase
+//!
+//! CMD32 and CMD33 select a range of write blocks, CMD38 erases it. The
+//! card signals busy on DAT0 until the erase finished, which can take
+//! seconds, the timeout is computed from the SD Status.
+
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{commands, executor::block_on, irq, registers::CardStatus, Error, USdhc};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Erase function of CMD38
+pub enum EraseKind {
+    /// The blocks read as all 0 or all 1 (SCR DATA_STAT_AFTER_ERASE)
+    Erase,
+    /// The card may keep the old data, the blocks are just released for
+    /// wear leveling (DISCARD_SUPPORT)
+    Discard,
+    /// Full User area Logical Erase, the blocks are erased logically and
+    /// read as in `Erase` (FULE_SUPPORT). Only for the whole user area.
+    Fule,
+}
+
+impl EraseKind {
+    fn argument(&self) -> u32 {
+        match self {
+            EraseKind::Erase => 0,
+            EraseKind::Discard => 1,
+            EraseKind::Fule => 2,
+        }
+    }
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Number of write blocks the card erases at once, 1 unless the CSD
+    /// requires erasing whole sectors (ERASE_BLK_EN = 0). None before the
+    /// CSD was read.
+    pub fn erase_unit(&self) -> Option<u32> {
+        self.csd.map(|csd| if csd.erase_blk_en() { 1 } else { csd.sector_size() })
+    }
+
+    /// Erases the blocks `start_lba..=end_lba`.
+    ///
+    /// `EraseKind::Erase` requires both ends aligned to `erase_unit`,
+    /// `Fule` the whole user area (`erase_all`), other ranges fail with
+    /// `Error::InvalidArgument`. `Discard` and `Fule` fail with
+    /// `Error::Unsupported` if the card doesn't report support in the SD
+    /// Status, every kind before the CSD was read. Returns after the card
+    /// released DAT0 and is back in the transfer state.
+    pub fn erase(&mut self, start_lba: u32, end_lba: u32, kind: EraseKind) -> Result<(), Error> {
+        block_on(self.erase_async(start_lba, end_lba, kind))
+    }
+
+    /// Async version of `erase`
+    pub async fn erase_async(&mut self, start_lba: u32, end_lba: u32, kind: EraseKind) -> Result<(), Error> {
+        let csd = self.csd.ok_or(Error::Unsupported)?;
+        let unit = self.erase_unit().ok_or(Error::Unsupported)?;
+        let blocks = csd.erase_range(start_lba, end_lba)?;
+        let misaligned = match kind {
+            // `end_lba + 1` aligned, without the overflow at u32::MAX
+            EraseKind::Erase => start_lba % unit != 0 || end_lba % unit != unit - 1,
+            EraseKind::Fule => start_lba != 0 || end_lba as u64 != csd.block_count() - 1,
+            EraseKind::Discard => false,
+        };
+        if misaligned {
+            return Err(Error::InvalidArgument);
+        }
+
+        let status = self.read_sd_status_async().await?;
+        match kind {
+            EraseKind::Discard if !status.discard_support() => return Err(Error::Unsupported),
+            EraseKind::Fule if !status.fule_support() => return Err(Error::Unsupported),
+            _ => {}
+        }
+        let timeout_ms = status.erase_timeout_ms(blocks);
+
+        let start = self.block_address(start_lba)?;
+        let end = self.block_address(end_lba)?;
+        // any other command between CMD32 and CMD38 clears the erase
+        // sequence, see `irq::BusGuard`
+        let bus = irq::BusGuard::claim();
+        let response = self.execute_async(commands::TagSectorStart::new(start)).await?;
+        CardStatus(response).check()?;
+        let response = self.execute_async(commands::TagSectorEnd::new(end)).await?;
+        CardStatus(response).check()?;
+
+        log::debug!("erase {}..={} {:?}, timeout {} ms", start_lba, end_lba, kind, timeout_ms);
+        let response = self.execute_async(commands::Erase::new(kind.argument())).await?;
+        drop(bus);
+        self.wait_while_busy_timeout_async(timeout_ms).await?;
+        CardStatus(response).check()?;
+        self.wait_for_transfer_state_async().await?;
+        Ok(())
+    }
+
+    /// Erases the whole user area, with FULE if the card supports it
+    pub fn erase_all(&mut self) -> Result<(), Error> {
+        let blocks = self.csd.ok_or(Error::Unsupported)?.block_count();
+        if blocks == 0 || blocks > u32::MAX as u64 {
+            return Err(Error::Unsupported);
+        }
+        let kind = if self.read_sd_status()?.fule_support() {
+            EraseKind::Fule
+        } else {
+            EraseKind::Erase
+        };
+        self.erase(0, (blocks - 1) as u32, kind)
+    }
+}
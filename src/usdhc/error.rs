@@ -0,0 +1,115 @@
+use super::{
+    adma::AdmaErrorStatus,
+    registers::{CardStatus, IoStatus},
+    regs::int_status,
+};
+
+/// All INT_STATUS bits that indicate a failed command
+pub const COMMAND_ERRORS: u32 = int_status::CTOE | int_status::CCE | int_status::CEBE | int_status::CIE;
+
+/// All INT_STATUS bits that indicate a failed data transfer
+pub const DATA_ERRORS: u32 =
+    int_status::DTOE | int_status::DCE | int_status::DEBE | int_status::AC12E | int_status::DMAE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Errors reported by the uSDHC or by the card
+pub enum Error {
+    /// No response within 64 SDCLK cycles (CTOE)
+    CommandTimeout,
+    /// CRC error in the command response (CCE)
+    CommandCrc,
+    /// End bit of the command response is 0 (CEBE)
+    CommandEndBit,
+    /// Command index of the response does not match (CIE)
+    CommandIndex,
+    /// Data timeout, see SYS_CTRL[DTOCV] (DTOE)
+    DataTimeout,
+    /// CRC error in the data phase (DCE)
+    DataCrc,
+    /// End bit of a data block is 0 (DEBE)
+    DataEndBit,
+    /// Auto CMD12 failed, holds AUTOCMD12_ERR_STATUS (AC12E)
+    AutoCmd12(u32),
+    /// Internal DMA transfer failed (DMAE)
+    Dma,
+    /// ADMA transfer failed, holds ADMA_ERR_STATUS
+    Adma(AdmaErrorStatus),
+    /// The card reported an error in its R1 status
+    Card(CardStatus),
+    /// The SDIO card reported an error in its R5 response
+    Io(IoStatus),
+    /// The card is password locked (CARD_IS_LOCKED), only `unlock` and
+    /// `force_erase` are accepted. Also reported if CMD42 failed
+    /// (LOCK_UNLOCK_FAILED), e.g. for a wrong password.
+    Locked,
+    /// A wait for the card or the uSDHC exceeded its retry or time limit
+    Timeout,
+    /// The card accepted CMD11 but the switch to 1.8V signaling failed, it
+    /// has to be power cycled before it answers again
+    VoltageSwitch,
+    /// The request can not be handled by the card or the driver
+    Unsupported,
+    /// An argument is out of range (block count, buffer size, ...)
+    InvalidArgument,
+}
+
+impl Error {
+    /// Maps the error bits of INT_STATUS into an `Error`.
+    ///
+    /// Command errors take precedence as the data phase can't be valid
+    /// without a valid command.
+    pub fn from_int_status(status: u32) -> Option<Self> {
+        if status & int_status::CTOE != 0 {
+            Some(Error::CommandTimeout)
+        } else if status & int_status::CCE != 0 {
+            Some(Error::CommandCrc)
+        } else if status & int_status::CEBE != 0 {
+            Some(Error::CommandEndBit)
+        } else if status & int_status::CIE != 0 {
+            Some(Error::CommandIndex)
+        } else if status & int_status::DTOE != 0 {
+            Some(Error::DataTimeout)
+        } else if status & int_status::DCE != 0 {
+            Some(Error::DataCrc)
+        } else if status & int_status::DEBE != 0 {
+            Some(Error::DataEndBit)
+        } else if status & int_status::AC12E != 0 {
+            Some(Error::AutoCmd12(0))
+        } else if status & int_status::DMAE != 0 {
+            Some(Error::Dma)
+        } else {
+            None
+        }
+    }
+
+    /// true if the error happened in the data phase and the card might
+    /// still be in the data or receive state
+    pub fn is_data_error(&self) -> bool {
+        matches!(
+            self,
+            Error::DataTimeout
+                | Error::DataCrc
+                | Error::DataEndBit
+                | Error::AutoCmd12(_)
+                | Error::Dma
+                | Error::Adma(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A failed write, with the number of blocks the card committed before the
+/// error (reported by ACMD22)
+pub struct WriteError {
+    pub cause: Error,
+    pub written_blocks: u32,
+}
+
+impl From<Error> for WriteError {
+    fn from(cause: Error) -> Self {
+        Self {
+            cause,
+            written_blocks: 0,
+        }
+    }
+}
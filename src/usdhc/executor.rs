@@ -0,0 +1,89 @@
+//! # Minimal executor
+//!
+//! The driver is written with `async fn`s so it can be awaited from any
+//! executor, the waits are woken from the USDHC1 ISR (`irq.rs`) or, in
+//! polling mode, stay runnable and are polled again right away.
+//!
+//! `block_on` runs one of these futures to completion for the blocking API.
+//! It sleeps with WFI while the future waits for an interrupt, on the host
+//! (tests) it polls again right away. `spin_on` never sleeps, it runs the
+//! command of the card interrupt, which may interrupt `block_on`.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+/// Set by the waker, cleared before every poll
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+static SPIN_VTABLE: RawWakerVTable = RawWakerVTable::new(spin_clone, drop, drop, drop);
+
+fn clone(_: *const ()) -> RawWaker {
+    RawWaker::new(core::ptr::null(), &VTABLE)
+}
+
+fn wake(_: *const ()) {
+    WOKEN.store(true, Ordering::Release);
+}
+
+fn drop(_: *const ()) {}
+
+fn spin_clone(_: *const ()) -> RawWaker {
+    RawWaker::new(core::ptr::null(), &SPIN_VTABLE)
+}
+
+/// Polls `future` until it's ready, sleeps between the polls until the
+/// future is woken.
+pub fn block_on<F: Future>(mut future: F) -> F::Output {
+    // Safety: `future` is shadowed and never moved again
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    // Safety: the vtable functions don't use the data pointer
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        WOKEN.store(false, Ordering::Release);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        sleep();
+    }
+}
+
+/// Polls `future` until it's ready without sleeping in between. Its waker
+/// does nothing, so it doesn't touch the state of a `block_on` it
+/// interrupted.
+pub fn spin_on<F: Future>(mut future: F) -> F::Output {
+    // Safety: `future` is shadowed and never moved again
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    // Safety: the vtable functions don't use the data pointer
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &SPIN_VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// Waits for the wake up of the future
+#[cfg(target_os = "none")]
+fn sleep() {
+    cortex_m::interrupt::free(|_| {
+        // the pending interrupt wakes the core even with interrupts
+        // masked, it's handled right after the critical section
+        if !WOKEN.load(Ordering::Acquire) {
+            cortex_m::asm::wfi();
+        }
+    });
+}
+
+/// The host tests have no interrupts, the future is polled again
+#[cfg(not(target_os = "none"))]
+fn sleep() {}
@@ -0,0 +1,58 @@
+//! # Vendor health reports (CMD56)
+//!
+//! Industrial cards report wear and lifetime through GEN_CMD, the argument
+//! and the layout of the 512 byte data block are vendor specific. The
+//! decoders check the signature of the block and return `None` for other
+//! layouts, so they can be tried one after another:
+//!
+//! ```ignore
+//! let mut block = [0u8; BLOCK_SIZE];
+//! usdhc.gen_cmd_read(SandiskHealth::ARGUMENT, &mut block)?;
+//! if let Some(health) = SandiskHealth::decode(&block) {
+//!     log::info!("{}% of life used", health.percent_used);
+//! }
+//! ```
+//!
+//! The decoders only work on the bytes (`report.rs`).
+
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    block::BLOCK_SIZE,
+    commands,
+    transfer::{StopMode, Transfer},
+    Error, USdhc,
+};
+
+mod report;
+
+pub use report::{MicronHealth, SandiskHealth};
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Reads a vendor specific block with CMD56, bit 0 of `argument` is
+    /// set by the driver
+    pub fn gen_cmd_read(&mut self, argument: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), Error> {
+        let transfer = Transfer::read(BLOCK_SIZE as u32, 1, StopMode::None);
+        self.read_data(commands::GenCmdRead::new(argument), &transfer, block)?
+            .check()?;
+        Ok(())
+    }
+
+    /// Writes a vendor specific block with CMD56, some cards expect a
+    /// request block before the report can be read
+    pub fn gen_cmd_write(&mut self, argument: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+        let transfer = Transfer::write(BLOCK_SIZE as u32, 1, StopMode::None);
+        self.write_data(commands::GenCmdWrite::new(argument), &transfer, block)?
+            .check()?;
+        Ok(())
+    }
+}
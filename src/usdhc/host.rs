@@ -0,0 +1,31 @@
+//! # Host build of the driver
+//!
+//! Module root of `usdhc` for the host tests
+//! (`cargo test --target x86_64-unknown-linux-gnu`). It only pulls in the
+//! modules that work on plain bytes and values or on the `Registers`
+//! backend, everything touching the uSDHC, the pins or the core
+//! peripherals directly stays in `mod.rs`.
+
+// most of the API is only used by the firmware
+#![allow(dead_code)]
+
+mod adma;
+mod buffer;
+pub mod cis;
+pub mod commands;
+mod crc;
+mod engine;
+mod error;
+mod executor;
+#[path = "health/report.rs"]
+mod health_report;
+#[path = "lock/data.rs"]
+mod lock_data;
+#[path = "mode_switch/eye_map.rs"]
+mod eye_map;
+pub mod registers;
+mod regs;
+#[cfg(test)]
+mod sim;
+
+pub use error::Error;
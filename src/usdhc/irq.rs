@@ -0,0 +1,433 @@
+//! # Interrupt driven transfer engine
+//!
+//! The USDHC1 ISR clears every signaled INT_STATUS bit and records it in
+//! a shared state and wakes the task waiting for them, see `executor.rs`
+//! for the blocking case that sleeps (WFI) in the meantime.
+//!
+//! - BRR/BWR: moves one watermark between DATA_BUFF_ACC_PORT and the
+//!   attached PIO buffer, so a CPU polling transfer runs inside the ISR.
+//!   Only signaled while a transfer driven by them runs, see
+//!   `prepare_transfer`
+//! - DINT: resumes a simple DMA transfer at the next buffer boundary
+//! - CINS/CRM: tracks the card presence and toggles between the insertion
+//!   and the removal interrupt, both bits follow the card detect level
+//! - CINT: SDIO card interrupt on DAT1, masked until the card was serviced.
+//!   The ISR only claims the bus, or defers the interrupt to the last
+//!   `BusGuard` if a command sequence is running. INT_PENDING is read with
+//!   CMD52 through the command engine after the shared state is released,
+//!   with the other interrupts enabled, then the handlers of the pending
+//!   functions are called, see `sdio.rs`
+//! - CC, TC, errors: completes the in-flight command/transfer
+
+use core::{
+    cell::RefCell,
+    task::{Poll, Waker},
+};
+use cortex_m::interrupt::Mutex;
+use teensy4_bsp::hal::ral::{self, usdhc::INT_STATUS};
+
+use super::{commands::IoRwDirect, engine, executor, registers::IoStatus, transfer::Hardware};
+
+/// CCCR Int Pending register
+const CCCR_INT_PENDING: u32 = 0x05;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Card detect events seen by the ISR
+pub enum CardEvent {
+    Inserted,
+    Removed,
+}
+
+/// Buffer of a CPU polling transfer, served from the ISR
+enum PioBuffer {
+    None,
+    Read {
+        segments: *mut [&'static mut [u8]],
+        segment: usize,
+        offset: usize,
+    },
+    Write {
+        segments: *const [&'static [u8]],
+        segment: usize,
+        offset: usize,
+    },
+}
+
+struct Engine {
+    /// INT_STATUS bits not yet consumed by the foreground
+    status: u32,
+    /// bytes moved by the ISR for the attached buffer
+    moved: usize,
+    pio: PioBuffer,
+    card_event: Option<CardEvent>,
+    /// task waiting in `poll_status`
+    waker: Option<Waker>,
+    /// SDIO interrupt handler of function 1..=7
+    io_handlers: [Option<fn()>; 8],
+    /// functions that signaled an interrupt, not yet taken by the foreground
+    io_pending: u8,
+    /// card interrupt arrived while the foreground used the bus
+    io_deferred: bool,
+    /// nesting depth of foreground command sequences, see `BusGuard`, and
+    /// the CMD52 of the card interrupt
+    bus_users: u8,
+}
+
+// Safety: the raw pointers in `PioBuffer` are only dereferenced inside the
+// ISR while the foreground keeps the buffer borrowed (attach/detach).
+unsafe impl Send for Engine {}
+
+static ENGINE: Mutex<RefCell<Engine>> = Mutex::new(RefCell::new(Engine {
+    status: 0,
+    moved: 0,
+    pio: PioBuffer::None,
+    card_event: None,
+    waker: None,
+    io_handlers: [None; 8],
+    io_pending: 0,
+    io_deferred: false,
+    bus_users: 0,
+}));
+
+/// SDIO functions to notify after a card interrupt was served
+struct IoDispatch {
+    pending: u8,
+    handlers: [Option<fn()>; 8],
+}
+
+impl IoDispatch {
+    const NONE: Self = Self {
+        pending: 0,
+        handlers: [None; 8],
+    };
+
+    /// Calls the handlers of the pending functions, outside of the critical
+    /// section so they can use the driver state themselves
+    fn call(self) {
+        for function in 1..8 {
+            if self.pending & (1 << function) != 0 {
+                if let Some(handler) = self.handlers[function] {
+                    handler();
+                }
+            }
+        }
+    }
+}
+
+/// Interrupt handler body of the USDHC1 vector
+pub fn on_interrupt() {
+    let usdhc = ral::usdhc::USDHC1;
+    let read_io = cortex_m::interrupt::free(|cs| {
+        let mut read_io = false;
+        let mut engine = ENGINE.borrow(cs).borrow_mut();
+        let signaled = unsafe {
+            ral::read_reg!(ral::usdhc, usdhc, INT_STATUS) & ral::read_reg!(ral::usdhc, usdhc, INT_SIGNAL_EN)
+        };
+        let mut record = signaled;
+
+        if signaled & (INT_STATUS::BRR::mask | INT_STATUS::BWR::mask) != 0 {
+            unsafe { ral::write_reg!(ral::usdhc, usdhc, INT_STATUS, signaled & (INT_STATUS::BRR::mask | INT_STATUS::BWR::mask)) };
+            if engine.serve_pio(usdhc) {
+                record &= !(INT_STATUS::BRR::mask | INT_STATUS::BWR::mask);
+            }
+        }
+
+        if signaled & INT_STATUS::DINT::mask != 0 {
+            unsafe {
+                if ral::read_reg!(ral::usdhc, usdhc, PROT_CTRL, DMASEL == 0) {
+                    let next = ral::read_reg!(ral::usdhc, usdhc, DS_ADDR);
+                    ral::write_reg!(ral::usdhc, usdhc, DS_ADDR, next);
+                    record &= !INT_STATUS::DINT::mask;
+                }
+            }
+        }
+
+        if signaled & INT_STATUS::CINT::mask != 0 {
+            // CINT follows DAT1, it stays masked until the function cleared
+            // its interrupt source, see `rearm_io`
+            unsafe {
+                ral::modify_reg!(ral::usdhc, usdhc, INT_SIGNAL_EN, CINTIEN: 0);
+                ral::modify_reg!(ral::usdhc, usdhc, INT_STATUS_EN, CINTSEN: 0);
+            }
+            read_io = engine.claim_io();
+            record &= !INT_STATUS::CINT::mask;
+        }
+
+        if signaled & INT_STATUS::CINS::mask != 0 {
+            engine.card_event = Some(CardEvent::Inserted);
+            unsafe { ral::modify_reg!(ral::usdhc, usdhc, INT_SIGNAL_EN, CINSIEN: 0, CRMIEN: 1) };
+        }
+        if signaled & INT_STATUS::CRM::mask != 0 {
+            engine.card_event = Some(CardEvent::Removed);
+            unsafe { ral::modify_reg!(ral::usdhc, usdhc, INT_SIGNAL_EN, CINSIEN: 1, CRMIEN: 0) };
+        }
+
+        unsafe { ral::write_reg!(ral::usdhc, usdhc, INT_STATUS, signaled & !(INT_STATUS::BRR::mask | INT_STATUS::BWR::mask)) };
+        engine.status |= record & !(INT_STATUS::CINS::mask | INT_STATUS::CRM::mask);
+        if engine.status != 0 {
+            if let Some(waker) = engine.waker.take() {
+                waker.wake();
+            }
+        }
+        read_io
+    });
+    if read_io {
+        // the ISR can't wait for its own interrupt, INT_STATUS is polled
+        serve_io(false);
+    }
+}
+
+impl Engine {
+    /// Claims the bus for reading INT_PENDING if it's idle, otherwise the
+    /// card interrupt is served when the last `BusGuard` is dropped
+    fn claim_io(&mut self) -> bool {
+        if self.bus_users == 0 {
+            self.bus_users = 1;
+            true
+        } else {
+            self.io_deferred = true;
+            false
+        }
+    }
+
+    /// Moves one watermark for the attached buffer, false if there is none
+    fn serve_pio(&mut self, usdhc: *const ral::usdhc::RegisterBlock) -> bool {
+        match &mut self.pio {
+            PioBuffer::None => false,
+            PioBuffer::Read {
+                segments,
+                segment,
+                offset,
+            } => {
+                // Safety: attached by `attach_read`, valid until `detach`
+                let segments = unsafe { &mut **segments };
+                let wml = unsafe { ral::read_reg!(ral::usdhc, usdhc, WTMK_LVL, RD_WML) }.max(1);
+                for _ in 0..wml {
+                    while *segment < segments.len() && *offset >= segments[*segment].len() {
+                        *segment += 1;
+                        *offset = 0;
+                    }
+                    if *segment >= segments.len() {
+                        break;
+                    }
+                    let word = unsafe { ral::read_reg!(ral::usdhc, usdhc, DATA_BUFF_ACC_PORT) }.to_le_bytes();
+                    for byte in word {
+                        while *segment < segments.len() && *offset >= segments[*segment].len() {
+                            *segment += 1;
+                            *offset = 0;
+                        }
+                        if *segment >= segments.len() {
+                            break;
+                        }
+                        segments[*segment][*offset] = byte;
+                        *offset += 1;
+                        self.moved += 1;
+                    }
+                }
+                true
+            }
+            PioBuffer::Write {
+                segments,
+                segment,
+                offset,
+            } => {
+                // Safety: attached by `attach_write`, valid until `detach`
+                let segments = unsafe { &**segments };
+                let wml = unsafe { ral::read_reg!(ral::usdhc, usdhc, WTMK_LVL, WR_WML) }.max(1);
+                for _ in 0..wml {
+                    let mut word = [0u8; 4];
+                    let mut filled = 0;
+                    while filled < 4 {
+                        while *segment < segments.len() && *offset >= segments[*segment].len() {
+                            *segment += 1;
+                            *offset = 0;
+                        }
+                        if *segment >= segments.len() {
+                            break;
+                        }
+                        word[filled] = segments[*segment][*offset];
+                        *offset += 1;
+                        filled += 1;
+                    }
+                    if filled == 0 {
+                        break;
+                    }
+                    self.moved += filled;
+                    unsafe { ral::write_reg!(ral::usdhc, usdhc, DATA_BUFF_ACC_PORT, u32::from_le_bytes(word)) };
+                }
+                true
+            }
+        }
+    }
+}
+
+/// Serves a card interrupt on the bus claimed with `claim_io`: reads
+/// INT_PENDING, records the pending functions and calls their handlers,
+/// everything outside of the critical section. Releases the bus.
+fn serve_io(irq_enabled: bool) {
+    let pending = read_int_pending(irq_enabled);
+    let dispatch = cortex_m::interrupt::free(|cs| {
+        let mut engine = ENGINE.borrow(cs).borrow_mut();
+        engine.bus_users -= 1;
+        if pending == 0 {
+            // nothing to clear, spurious or already handled
+            rearm_io(ral::usdhc::USDHC1);
+            return IoDispatch::NONE;
+        }
+        engine.io_pending |= pending;
+        if let Some(waker) = engine.waker.take() {
+            waker.wake();
+        }
+        IoDispatch {
+            pending,
+            handlers: engine.io_handlers,
+        }
+    });
+    dispatch.call();
+}
+
+/// Sends CMD52 for CCCR INT_PENDING through the command engine, returns the
+/// pending functions (bit n = function n) or 0 if the command failed
+fn read_int_pending(irq_enabled: bool) -> u8 {
+    let cmd = IoRwDirect::read(0, CCCR_INT_PENDING);
+    let response = executor::spin_on(engine::issue(&mut Hardware::usdhc1(irq_enabled), &cmd));
+    match response.and_then(|response| IoStatus(response).check()) {
+        Ok(response) => response.data() & 0xFE,
+        Err(err) => {
+            log::warn!("reading INT_PENDING failed {:?}", err);
+            0
+        }
+    }
+}
+
+/// Attaches the buffer of a CPU polling read, has to be done before the
+/// command is sent so the first BRR is served.
+///
+/// # Safety
+///
+/// The segments have to stay borrowed until `detach` is called.
+pub unsafe fn attach_read(segments: &mut [&mut [u8]]) {
+    let segments = segments as *mut [&mut [u8]] as *mut [&'static mut [u8]];
+    cortex_m::interrupt::free(|cs| {
+        let mut engine = ENGINE.borrow(cs).borrow_mut();
+        engine.moved = 0;
+        engine.pio = PioBuffer::Read {
+            segments,
+            segment: 0,
+            offset: 0,
+        };
+    });
+}
+
+/// Attaches the buffer of a CPU polling write, see `attach_read`
+///
+/// # Safety
+///
+/// The segments have to stay borrowed until `detach` is called.
+pub unsafe fn attach_write(segments: &[&[u8]]) {
+    let segments = segments as *const [&[u8]] as *const [&'static [u8]];
+    cortex_m::interrupt::free(|cs| {
+        let mut engine = ENGINE.borrow(cs).borrow_mut();
+        engine.moved = 0;
+        engine.pio = PioBuffer::Write {
+            segments,
+            segment: 0,
+            offset: 0,
+        };
+    });
+}
+
+/// Detaches the PIO buffer, returns the number of bytes the ISR moved
+pub fn detach() -> usize {
+    cortex_m::interrupt::free(|cs| {
+        let mut engine = ENGINE.borrow(cs).borrow_mut();
+        engine.pio = PioBuffer::None;
+        engine.moved
+    })
+}
+
+/// Consumes and returns the recorded `mask` bits, registers `waker` for
+/// the next interrupt if none of them is set yet.
+pub fn poll_status(mask: u32, waker: &Waker) -> Poll<u32> {
+    cortex_m::interrupt::free(|cs| {
+        let mut engine = ENGINE.borrow(cs).borrow_mut();
+        let hit = engine.status & mask;
+        if hit != 0 {
+            engine.status &= !hit;
+            Poll::Ready(hit)
+        } else {
+            match &engine.waker {
+                Some(current) if current.will_wake(waker) => {}
+                _ => engine.waker = Some(waker.clone()),
+            }
+            Poll::Pending
+        }
+    })
+}
+
+/// Enables the card interrupt (CINT) again
+fn rearm_io(usdhc: *const ral::usdhc::RegisterBlock) {
+    unsafe {
+        ral::write_reg!(ral::usdhc, usdhc, INT_STATUS, INT_STATUS::CINT::mask);
+        ral::modify_reg!(ral::usdhc, usdhc, INT_STATUS_EN, CINTSEN: 1);
+        ral::modify_reg!(ral::usdhc, usdhc, INT_SIGNAL_EN, CINTIEN: 1);
+    }
+}
+
+/// Re-arms the card interrupt after the functions cleared their sources
+pub fn rearm_io_interrupt() {
+    cortex_m::interrupt::free(|_| rearm_io(ral::usdhc::USDHC1));
+}
+
+/// Registers the handler of an SDIO function, called from the ISR
+pub fn set_io_handler(function: u8, handler: Option<fn()>) {
+    cortex_m::interrupt::free(|cs| ENGINE.borrow(cs).borrow_mut().io_handlers[function as usize & 0b111] = handler);
+}
+
+/// Functions that signaled an interrupt since the previous call, bit n = function n
+pub fn take_io_pending() -> u8 {
+    cortex_m::interrupt::free(|cs| core::mem::take(&mut ENGINE.borrow(cs).borrow_mut().io_pending))
+}
+
+/// Marks the bus as used by the foreground while alive, a card interrupt
+/// arriving in the meantime is served when the last guard is dropped.
+///
+/// Held across every command sequence the card has to see without a CMD52
+/// in between: CMD55 and the application command, CMD23 and the data
+/// command, the data command and the CMD12/CMD13 after it.
+pub struct BusGuard(());
+
+impl BusGuard {
+    pub fn claim() -> Self {
+        cortex_m::interrupt::free(|cs| ENGINE.borrow(cs).borrow_mut().bus_users += 1);
+        Self(())
+    }
+}
+
+impl Drop for BusGuard {
+    fn drop(&mut self) {
+        let read_io = cortex_m::interrupt::free(|cs| {
+            let mut engine = ENGINE.borrow(cs).borrow_mut();
+            engine.bus_users -= 1;
+            if engine.bus_users == 0 && engine.io_deferred {
+                engine.io_deferred = false;
+                engine.claim_io()
+            } else {
+                false
+            }
+        });
+        if read_io {
+            serve_io(true);
+        }
+    }
+}
+
+/// Drops all recorded bits, before a new command is started
+pub fn clear(mask: u32) {
+    cortex_m::interrupt::free(|cs| ENGINE.borrow(cs).borrow_mut().status &= !mask);
+}
+
+/// Last card detect event, if any happened since the previous call
+pub fn take_card_event() -> Option<CardEvent> {
+    cortex_m::interrupt::free(|cs| ENGINE.borrow(cs).borrow_mut().card_event.take())
+}
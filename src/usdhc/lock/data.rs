@@ -0,0 +1,93 @@
+//! # Lock card data structure
+//!
+//! The CMD42 data block, kept free of the uSDHC so the layout is tested on
+//! the host.
+
+use super::Error;
+
+/// Maximum length of a password
+pub const MAX_PASSWORD_LEN: usize = 16;
+
+pub const SET_PWD: u8 = 1 << 0;
+pub const CLR_PWD: u8 = 1 << 1;
+pub const LOCK_UNLOCK: u8 = 1 << 2;
+pub const ERASE: u8 = 1 << 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Data block of CMD42, the block length is `as_bytes().len()`
+pub struct LockData {
+    bytes: [u8; 2 + 2 * MAX_PASSWORD_LEN],
+    len: usize,
+}
+
+impl LockData {
+    /// Flag byte, PWDS_LEN and the old password followed by the new one.
+    /// The forced erase (`ERASE`) only sends the flag byte. Fails with
+    /// `Error::InvalidArgument` if a password is longer than
+    /// `MAX_PASSWORD_LEN`.
+    pub fn new(flags: u8, password: &[u8], new_password: &[u8]) -> Result<Self, Error> {
+        if password.len() > MAX_PASSWORD_LEN || new_password.len() > MAX_PASSWORD_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        let mut bytes = [0u8; 2 + 2 * MAX_PASSWORD_LEN];
+        let pwds_len = password.len() + new_password.len();
+        bytes[0] = flags;
+        bytes[1] = pwds_len as u8;
+        bytes[2..2 + password.len()].copy_from_slice(password);
+        bytes[2 + password.len()..2 + pwds_len].copy_from_slice(new_password);
+        let len = if flags == ERASE { 1 } else { 2 + pwds_len };
+        Ok(Self { bytes, len })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_password_is_set_without_old_one() {
+        let data = LockData::new(SET_PWD, &[], b"1234").unwrap();
+        assert_eq!(data.as_bytes(), b"\x01\x041234");
+    }
+
+    #[test]
+    fn replace_sends_old_and_new_password() {
+        let data = LockData::new(SET_PWD, b"old", b"secret").unwrap();
+        assert_eq!(data.as_bytes(), b"\x01\x09oldsecret");
+    }
+
+    #[test]
+    fn clear_lock_and_unlock_send_one_password() {
+        assert_eq!(LockData::new(CLR_PWD, b"pw", &[]).unwrap().as_bytes(), b"\x02\x02pw");
+        assert_eq!(LockData::new(LOCK_UNLOCK, b"pw", &[]).unwrap().as_bytes(), b"\x04\x02pw");
+        assert_eq!(LockData::new(0, b"pw", &[]).unwrap().as_bytes(), b"\x00\x02pw");
+    }
+
+    #[test]
+    fn force_erase_is_only_the_flag_byte() {
+        assert_eq!(LockData::new(ERASE, &[], &[]).unwrap().as_bytes(), [ERASE]);
+    }
+
+    #[test]
+    fn longest_passwords_fill_the_block() {
+        let old = [0xA5; MAX_PASSWORD_LEN];
+        let new = [0x5A; MAX_PASSWORD_LEN];
+        let data = LockData::new(SET_PWD, &old, &new).unwrap();
+        let bytes = data.as_bytes();
+        assert_eq!(bytes.len(), 2 + 2 * MAX_PASSWORD_LEN);
+        assert_eq!(bytes[1] as usize, 2 * MAX_PASSWORD_LEN);
+        assert_eq!(&bytes[2..18], old);
+        assert_eq!(&bytes[18..], new);
+    }
+
+    #[test]
+    fn too_long_passwords_are_rejected() {
+        let long = [0; MAX_PASSWORD_LEN + 1];
+        assert_eq!(LockData::new(SET_PWD, &long, b"1"), Err(Error::InvalidArgument));
+        assert_eq!(LockData::new(SET_PWD, b"1", &long), Err(Error::InvalidArgument));
+    }
+}
@@ -0,0 +1,115 @@
+//! # Password protection (CMD42)
+//!
+//! The lock card data structure is sent as a single data block:
+//!
+//! | Byte   | Field                                               |
+//! | ------ | --------------------------------------------------- |
+//! | 0      | [3] ERASE, [2] LOCK_UNLOCK, [1] CLR_PWD, [0] SET_PWD |
+//! | 1      | PWDS_LEN, total length of the password data         |
+//! | 2..    | password data, old password followed by the new one |
+//!
+//! The block length is set to the structure size with CMD16 and restored to
+//! `BLOCK_SIZE` afterwards. A locked card only accepts the basic commands
+//! and CMD42, block reads and writes fail with `Error::Locked`.
+
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    block::BLOCK_SIZE,
+    commands,
+    registers::CardStatus,
+    transfer::{StopMode, Transfer},
+    Error, USdhc,
+};
+
+mod data;
+
+use data::{LockData, CLR_PWD, ERASE, LOCK_UNLOCK, SET_PWD};
+pub use data::MAX_PASSWORD_LEN;
+
+/// Busy time of the forced erase
+const FORCE_ERASE_TIMEOUT_MS: u32 = 3 * 60 * 1000;
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// True if the card reported CARD_IS_LOCKED
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Sets a new password, `old` is empty if the card has none yet.
+    ///
+    /// The card stays unlocked until the next power cycle or `lock`.
+    pub fn set_password(&mut self, old: &[u8], new: &[u8]) -> Result<(), Error> {
+        if new.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        self.lock_unlock(SET_PWD, old, new)
+    }
+
+    /// Removes the password
+    pub fn clear_password(&mut self, password: &[u8]) -> Result<(), Error> {
+        self.lock_unlock(CLR_PWD, password, &[])
+    }
+
+    /// Locks the card, it has to have a password
+    pub fn lock(&mut self, password: &[u8]) -> Result<(), Error> {
+        self.lock_unlock(LOCK_UNLOCK, password, &[])
+    }
+
+    /// Unlocks the card and reads the SCR if `identify_card` stopped at the
+    /// locked card
+    pub fn unlock(&mut self, password: &[u8]) -> Result<(), Error> {
+        self.lock_unlock(0, password, &[])?;
+        if self.scr.is_none() {
+            self.scr = Some(self.read_scr()?);
+        }
+        Ok(())
+    }
+
+    /// Erases the whole card including the password, for cards with a lost
+    /// password. The card is busy for up to 3 minutes, fails with
+    /// `Error::Timeout` if it's still busy afterwards.
+    pub fn force_erase(&mut self) -> Result<(), Error> {
+        self.lock_unlock(ERASE, &[], &[])?;
+        if self.scr.is_none() {
+            self.scr = Some(self.read_scr()?);
+        }
+        Ok(())
+    }
+
+    fn lock_unlock(&mut self, flags: u8, password: &[u8], new_password: &[u8]) -> Result<(), Error> {
+        let data = LockData::new(flags, password, new_password)?;
+        let len = data.as_bytes().len();
+
+        let status = self.execute(commands::SetBlocklen::new(len as u32))?;
+        CardStatus(status).check()?;
+        let stop = if flags == ERASE { StopMode::LongBusy } else { StopMode::None };
+        let transfer = Transfer::write(len as u32, 1, stop);
+        let result = self
+            .write_data(commands::LockUnlock::new(), &transfer, data.as_bytes())
+            .and_then(|status| {
+                if flags == ERASE {
+                    self.wait_while_busy_timeout(FORCE_ERASE_TIMEOUT_MS)?;
+                }
+                Ok(status)
+            });
+        let status = self.execute(commands::SetBlocklen::new(BLOCK_SIZE as u32));
+        result?.check()?;
+        CardStatus(status?).check()?;
+
+        // LOCK_UNLOCK_FAILED is reported in the status after the command
+        let status = self.wait_for_transfer_state()?;
+        self.locked = status.is_set(CardStatus::CARD_IS_LOCKED);
+        log::debug!("lock/unlock {:x}, locked {}", flags, self.locked);
+        Ok(())
+    }
+}
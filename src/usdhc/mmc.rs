@@ -0,0 +1,145 @@
+//! # eMMC configuration
+//!
+//! eMMC devices are configured through the EXT_CSD, bytes are written with
+//! CMD6 SWITCH. The card is busy on DAT0 after the switch, SWITCH_ERROR is
+//! reported by the following CMD13.
+//!
+//! The bus width is probed with the bus test (JEDEC 84-B51 A.8.3): after
+//! switching card and controller, CMD19 BUSTEST_W sends a pattern and
+//! CMD14 BUSTEST_R has to return it inverted on every line.
+
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    commands::{self, ExtCsd},
+    registers::{CardStatus, ExtendedCsd},
+    transfer::{StopMode, Transfer},
+    BusWidth, CardMode, Error, USdhc,
+};
+
+/// EXT_CSD BUS_WIDTH [183]
+pub const EXT_CSD_BUS_WIDTH: u8 = 183;
+
+/// Bus test pattern for 8 data lines, one byte per bit time
+const BUS_TEST_8BIT: [u8; 8] = [0x55, 0xAA, 0, 0, 0, 0, 0, 0];
+/// Bus test pattern for 4 data lines, two bit times per byte
+const BUS_TEST_4BIT: [u8; 4] = [0x5A, 0, 0, 0];
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Reads the EXT_CSD with CMD8
+    pub fn read_ext_csd(&mut self) -> Result<ExtendedCsd, Error> {
+        let mut bytes = [0u8; 512];
+        let transfer = Transfer::read(512, 1, StopMode::None);
+        self.read_data(commands::SendExtCsdBlock::new(), &transfer, &mut bytes)?
+            .check()?;
+        Ok(ExtendedCsd(bytes))
+    }
+
+    /// Writes `value` into the EXT_CSD byte `index` with CMD6 and checks
+    /// SWITCH_ERROR once the card left the busy state
+    pub fn mmc_switch(&mut self, index: u8, value: u8) -> Result<(), Error> {
+        self.send_mmc_switch(index, value)?;
+        self.check_mmc_switch()
+    }
+
+    /// CMD6 without the CMD13 check, for timing switches the host has to
+    /// follow before it talks to the device again
+    pub(crate) fn send_mmc_switch(&mut self, index: u8, value: u8) -> Result<(), Error> {
+        let status = self.execute(commands::Switch::new(ExtCsd::WriteByte, index, value))?;
+        CardStatus(status).check()?;
+        Ok(())
+    }
+
+    /// Polls CMD13 after CMD6 and checks SWITCH_ERROR
+    pub(crate) fn check_mmc_switch(&mut self) -> Result<(), Error> {
+        let status = self.wait_for_transfer_state()?;
+        if status.is_set(CardStatus::SWITCH_ERROR) {
+            return Err(Error::Card(status));
+        }
+        Ok(())
+    }
+
+    /// Selects the widest working data bus of an eMMC, tries 8, 4 and 1 bit
+    /// as far as they are wired (`Builder::bus_width`).
+    ///
+    /// Returns the selected width, it's also recorded as `CardMode::Mmc1`,
+    /// `Mmc4` or `Mmc8`.
+    pub fn select_mmc_bus_width(&mut self) -> Result<BusWidth, Error> {
+        for width in [BusWidth::Eight, BusWidth::Four] {
+            if width > self.wired_bus_width {
+                continue;
+            }
+            match self.try_mmc_bus_width(width) {
+                Ok(()) => return Ok(width),
+                Err(err) => log::warn!("{:?} bus test failed {:?}", width, err),
+            }
+        }
+        self.set_mmc_bus_width(BusWidth::One)?;
+        Ok(BusWidth::One)
+    }
+
+    fn try_mmc_bus_width(&mut self, width: BusWidth) -> Result<(), Error> {
+        self.set_mmc_bus_width(width)?;
+        let result = self.mmc_bus_test(width);
+        if result.is_err() {
+            // the card may be stuck in the bus test, bring it back before
+            // the next width is tried
+            self.reset_data_line()?;
+            self.wait_for_transfer_state()?;
+        }
+        result
+    }
+
+    /// Switches EXT_CSD BUS_WIDTH (SDR: 0 = 1 bit, 1 = 4 bit, 2 = 8 bit)
+    /// and PROT_CTRL[DTW]
+    fn set_mmc_bus_width(&mut self, width: BusWidth) -> Result<(), Error> {
+        self.mmc_switch(EXT_CSD_BUS_WIDTH, width.dtw() as u8)?;
+        self.set_data_transfer_width(width);
+        self.mode = match width {
+            BusWidth::One => CardMode::Mmc1,
+            BusWidth::Four => CardMode::Mmc4,
+            BusWidth::Eight => CardMode::Mmc8,
+        };
+        log::debug!("mmc bus width {:?}", width);
+        Ok(())
+    }
+
+    /// Sends the pattern for `width` with BUSTEST_W and checks that
+    /// BUSTEST_R returns it inverted
+    fn mmc_bus_test(&mut self, width: BusWidth) -> Result<(), Error> {
+        let pattern: &[u8] = match width {
+            BusWidth::Eight => &BUS_TEST_8BIT,
+            BusWidth::Four => &BUS_TEST_4BIT,
+            BusWidth::One => return Ok(()),
+        };
+        let len = pattern.len();
+
+        // the card stays in the bus test state until BUSTEST_R
+        let transfer = Transfer::write(len as u32, 1, StopMode::NoStatus);
+        self.write_data(commands::BusTestWrite::new(), &transfer, pattern)?
+            .check()?;
+
+        let mut response = [0u8; 8];
+        let transfer = Transfer::read(len as u32, 1, StopMode::None);
+        self.read_data(commands::BusTestRead::new(), &transfer, &mut response[..len])?
+            .check()?;
+
+        // only the bit times carrying the pattern are compared
+        let valid = len / 4;
+        if pattern[..valid].iter().zip(&response[..valid]).all(|(p, r)| p ^ r == 0xFF) {
+            Ok(())
+        } else {
+            log::debug!("bus test {:x?} -> {:x?}", pattern, &response[..len]);
+            Err(Error::DataCrc)
+        }
+    }
+}
@@ -1,14 +1,53 @@
+mod adma;
+mod block;
 mod block_transfer;
 mod buffer;
+mod bus;
+mod cache;
+mod card;
+pub mod cis;
+mod clock;
 pub mod commands;
 mod constants;
 mod crc;
+mod edma;
+mod engine;
+mod erase;
+mod error;
+mod executor;
+pub mod health;
+mod irq;
+mod lock;
+mod mmc;
 mod mode_switch;
+pub mod registers;
+mod regs;
 mod sd_card;
+mod sdio;
+mod speed;
+mod storage;
+mod transfer;
+mod uhs;
+mod write_protect;
 
 use core::marker::PhantomData;
 
+pub use adma::{Adma2Descriptor, Adma2Table, AdmaErrorStatus};
+pub use block::BLOCK_SIZE;
+pub use block_transfer::{DataTransfer, IoAddressMode, IoChunk};
+pub use buffer::{TransferMode, Watermark};
+pub use cache::BOUNCE_BUFFER_LEN;
 pub use constants::*;
+pub use edma::ExternalDma;
+pub use erase::EraseKind;
+pub use error::{Error, WriteError};
+pub use executor::block_on;
+pub use irq::CardEvent;
+pub use lock::MAX_PASSWORD_LEN;
+pub use mode_switch::{DelayChain, EyeMap, TuningCommand};
+pub use storage::StorageError;
+pub use uhs::UhsMode;
+pub use write_protect::PermanentWriteProtect;
 use hal::{
     gpio,
     iomuxc::{self, consts::U1},
@@ -108,6 +147,9 @@ impl<M> Uninitialized<M> {
 pub struct Builder<M> {
     _module: PhantomData<M>,
     usdhc_reg: ral::usdhc::Instance,
+    bus_width: BusWidth,
+    signaling_1v8: bool,
+    mmc_driver_type: u8,
 }
 
 impl<M> Builder<M>
@@ -121,9 +163,35 @@ where
         Self {
             _module: PhantomData,
             usdhc_reg,
+            bus_width: BusWidth::Four,
+            signaling_1v8: false,
+            mmc_driver_type: 0,
         }
     }
 
+    /// Number of data lines wired to the card, 4 by default.
+    ///
+    /// `BusWidth::One` if only DAT0 is connected, `BusWidth::Eight` for an
+    /// eMMC with DAT4..DAT7 muxed by the application.
+    pub fn bus_width(mut self, width: BusWidth) -> Self {
+        self.bus_width = width;
+        self
+    }
+
+    /// The board can supply the card pads with 1.8V, switched by
+    /// VEND_SPEC[VSELECT]. Off by default, UHS-I needs it.
+    pub fn signaling_1v8(mut self, supported: bool) -> Self {
+        self.signaling_1v8 = supported;
+        self
+    }
+
+    /// Preferred eMMC driver type in HS200 (EXT_CSD DRIVER_STRENGTH), 0
+    /// (50 Ohm) by default. Devices that don't support it use type 0.
+    pub fn mmc_driver_type(mut self, driver_type: u8) -> Self {
+        self.mmc_driver_type = driver_type;
+        self
+    }
+
     pub fn build<CMD, CLK, D0, D1, D2, D3>(
         self,
         cmd: CMD,
@@ -144,7 +212,11 @@ where
         ral::modify_reg!(ral::usdhc, self.usdhc_reg, MIX_CTRL, DTDSEL: 0);
         let pins = USdhcPins::new(cmd, clk, d0, d1, d2, d3);
 
-        USdhc::new(self.usdhc_reg, pins)
+        let mut usdhc = USdhc::new(self.usdhc_reg, pins);
+        usdhc.wired_bus_width = self.bus_width;
+        usdhc.wired_1v8 = self.signaling_1v8;
+        usdhc.mmc_driver_type = self.mmc_driver_type;
+        usdhc
     }
 }
 
@@ -204,6 +276,20 @@ where
         iomuxc::alternate(&mut self.d2, 5);
         iomuxc::alternate(&mut self.d3, 5);
     }
+
+    /// Pad speed and drive strength of all pins, faster edges for SDCLK
+    /// above 25 MHz
+    pub fn set_pad_speed(&mut self, speed: iomuxc::Speed, strength: iomuxc::DriveStrength) {
+        let config = iomuxc::Config::modify()
+            .set_speed(speed)
+            .set_drive_strength(strength);
+        iomuxc::configure(&mut self.cmd, config);
+        iomuxc::configure(&mut self.clk, config);
+        iomuxc::configure(&mut self.d0, config);
+        iomuxc::configure(&mut self.d1, config);
+        iomuxc::configure(&mut self.d2, config);
+        iomuxc::configure(&mut self.d3, config);
+    }
 }
 
 pub struct USdhc<M, CMD, CLK, D0, D1, D2, D3>
@@ -220,6 +306,23 @@ where
     mode: CardMode,
     pins: USdhcPins<M, CMD, CLK, D0, D1, D2, D3>,
     sd_clk_khz: u32,
+    rca: u16,
+    high_capacity: bool,
+    scr: Option<registers::Scr>,
+    csd: Option<registers::Csd>,
+    transfer_mode: TransferMode,
+    watermark: Watermark,
+    adma_table: adma::Adma2Table<{ adma::ADMA2_TABLE_LEN }>,
+    edma: Option<ExternalDma>,
+    bounce: cache::BounceBuffer,
+    io_block_size: [u16; 8],
+    irq_enabled: bool,
+    pre_erase: bool,
+    locked: bool,
+    wired_bus_width: BusWidth,
+    wired_1v8: bool,
+    mmc_driver_type: u8,
+    eye_map: Option<mode_switch::EyeMap>,
 }
 
 impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
@@ -234,11 +337,29 @@ where
 {
     fn new(usdhc: ral::usdhc::Instance, mut pins: USdhcPins<M, CMD, CLK, D0, D1, D2, D3>) -> Self {
         pins.enable();
+        clock::enable();
         Self {
             usdhc,
             pins,
             mode: CardMode::Unknown,
             sd_clk_khz: 0,
+            rca: 0,
+            high_capacity: false,
+            scr: None,
+            csd: None,
+            transfer_mode: TransferMode::CpuPolling,
+            watermark: Watermark::default(),
+            adma_table: adma::Adma2Table::new(),
+            edma: None,
+            bounce: cache::BounceBuffer::new(),
+            io_block_size: [0; 8],
+            irq_enabled: false,
+            pre_erase: false,
+            locked: false,
+            wired_bus_width: BusWidth::Four,
+            wired_1v8: false,
+            mmc_driver_type: 0,
+            eye_map: None,
         }
     }
 
@@ -250,7 +371,11 @@ where
         log::debug!("fq {}, divider {}, res {}", fq, divider, res);
         return res;
     }
-    fn set_sd_clk(&mut self, max_sd_clk: u32, ccm: &mut hal::ccm::Handle) {
+    /// Sets SDCLK to at most `max_sd_clk` and MIX_CTRL[DDR_EN] to `ddr`.
+    ///
+    /// In DDR mode the prescaler divides by twice the SDR value and
+    /// SDCLKFS 0 is reserved, the smallest prescaler is base/4.
+    fn set_sd_clk(&mut self, max_sd_clk: u32, ddr: bool, ccm: &mut hal::ccm::Handle) {
         let dvs_limit: u32 = 0x10;
         let sd_clk_fs_limit: u32 = 0x100;
         let base: u32 = Self::base_clock(ccm);
@@ -265,17 +390,17 @@ where
         // sdclkfs >>= 1;
         // dvs--;
 
-        let mut sd_clk_fs: u32 = 1;
-        while (base / (sd_clk_fs * dvs_limit) > max_sd_clk) && (sd_clk_fs < sd_clk_fs_limit) {
+        let (mut sd_clk_fs, ddr_div): (u32, u32) = if ddr { (2, 2) } else { (1, 1) };
+        while (base / (sd_clk_fs * ddr_div * dvs_limit) > max_sd_clk) && (sd_clk_fs < sd_clk_fs_limit) {
             sd_clk_fs <<= 1;
         }
 
         let mut dvs: u32 = 1;
-        while (base / (sd_clk_fs * dvs) > max_sd_clk) && (dvs < dvs_limit) {
+        while (base / (sd_clk_fs * ddr_div * dvs) > max_sd_clk) && (dvs < dvs_limit) {
             dvs += 1;
         }
 
-        self.sd_clk_khz = base / (1000 * sd_clk_fs * dvs);
+        self.sd_clk_khz = base / (1000 * sd_clk_fs * ddr_div * dvs);
         log::debug!("clk khz {}", self.sd_clk_khz);
 
         sd_clk_fs = sd_clk_fs >> 1;
@@ -303,7 +428,8 @@ where
             FRC_SDCLK_ON: 0
         );
 
-        // Change dividers.
+        // Change the mode and the dividers together, they depend on each other.
+        ral::modify_reg!(ral::usdhc, self.usdhc, MIX_CTRL, DDR_EN: ddr as u32);
         ral::modify_reg!(
             ral::usdhc,
             self.usdhc,
@@ -334,7 +460,7 @@ where
 
             self.reset_and_wait();
 
-            self.set_sd_clk(constants::SD_MAX_INIT_RATE_HZ, ccm);
+            self.set_sd_clk(constants::SD_MAX_INIT_RATE_HZ, false, ccm);
 
             self.pins.enable();
 
@@ -344,29 +470,11 @@ where
 
     pub fn init(&mut self, ccm: &mut hal::ccm::Handle) {
         self.set_init_mode(ccm);
+        self.set_watermark(self.watermark);
 
         #[cortex_m_rt::interrupt]
         fn USDHC1() {
-            unsafe {
-                // WHY ?
-                ral::write_reg!(ral::usdhc, ral::usdhc::USDHC1, INT_SIGNAL_EN, 0);
-
-                let int = ral::read_reg!(ral::usdhc, ral::usdhc::USDHC1, INT_STATUS);
-                log::debug!("int {:b}", int);
-                ral::write_reg!(ral::usdhc, ral::usdhc::USDHC1, INT_STATUS, int);
-                ral::modify_reg!(ral::usdhc, ral::usdhc::USDHC1, MIX_CTRL, AC23EN: 0, DMAEN: DMAEN_0);
-            }
-
-            /*
-                1_1000_0000_0000_0000
-
-            SDHC_IRQSIGEN = 0;
-            m_irqstat = SDHC_IRQSTAT;
-            SDHC_IRQSTAT = m_irqstat;
-            #if defined(__IMXRT1062__)
-            SDHC_MIX_CTRL &= ~(SDHC_MIX_CTRL_AC23EN | SDHC_MIX_CTRL_DMAEN);
-            #endif
-            */
+            irq::on_interrupt();
         }
         self.enable_interrupts();
 
@@ -395,10 +503,13 @@ where
         */
     }
 
+    /// Switches to the interrupt driven engine (see `irq.rs`): the foreground
+    /// sleeps while the ISR serves the transfer and records the status.
     pub fn enable_interrupts(&mut self) {
         cortex_m::peripheral::NVIC::mask(bsp::interrupt::USDHC1);
 
         // Enable desired IRQSTAT bits.
+        let inserted = ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE, CINST);
         ral::write_reg!(
             ral::usdhc,
             self.usdhc,
@@ -415,56 +526,100 @@ where
             CTOEIEN: CTOEIEN_1,
             DINTIEN: DINTIEN_1,
             CCIEN: CCIEN_1,
-            CINSIEN: CINSIEN_1,
-            CRMIEN: CRMIEN_1
+            // BRR/BWR are signaled per transfer, see `prepare_transfer`
+            BRRIEN: BRRIEN_0,
+            BWRIEN: BWRIEN_0,
+            CINSIEN: inserted ^ 1,
+            CRMIEN: inserted
         );
 
         ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS_EN, 0xffff_ffff);
 
         cortex_m::interrupt::free(|_cs| {
+            self.irq_enabled = true;
             // Safety: invoked in a critical section that also prepares the ISR
             // shared memory. ISR memory is ready by the time the ISR runs.
             unsafe { cortex_m::peripheral::NVIC::unmask(bsp::interrupt::USDHC1) };
         });
     }
-    pub fn wait_for_card(&mut self) {
-        while self.is_card_busy() {
-            // log::debug!("---")
-        }
+
+    /// Back to polling INT_STATUS in the foreground
+    pub fn disable_interrupts(&mut self) {
+        cortex_m::peripheral::NVIC::mask(bsp::interrupt::USDHC1);
+        ral::write_reg!(ral::usdhc, self.usdhc, INT_SIGNAL_EN, 0);
+        self.irq_enabled = false;
+    }
+
+    /// Card insertion/removal seen by the ISR since the last call
+    pub fn card_event(&mut self) -> Option<irq::CardEvent> {
+        irq::take_card_event()
+    }
+
+    /// wait until the previous command no longer inhibits CMD, fails with
+    /// `Error::Timeout` if the uSDHC is stuck
+    pub fn wait_for_card(&mut self) -> Result<(), Error> {
+        let busy = |state: u32| state & regs::pres_state::CIHB != 0;
+        block_on(engine::wait_pres_state(&mut self.registers(), busy, engine::COMMAND_INHIBIT_TIMEOUT_US))
     }
     pub fn wait_for_command_complete(&mut self) {
         while !self.is_command_comlete() {}
     }
 
     pub fn send_command(&mut self, cmd: impl commands::SdCommand) -> bool {
-        if cmd.req_app_cmd() {
-            log::debug!("send req app cmd");
-            // recursive call with AppCmd (CMD55)
-            self.send_command(commands::AppCmd::new(0));
+        match self.execute(cmd) {
+            Ok(_) => true,
+            Err(err) => {
+                log::debug!("send cmd failed {:?}", err);
+                false
+            }
         }
-        log::debug!("send cmd: {}", cmd.cmd_id());
+    }
 
-        self.wait_for_card();
+    /// Selects how the data of block commands is moved, CPU polling or internal DMA
+    pub fn set_transfer_mode(&mut self, mode: TransferMode) {
+        self.transfer_mode = mode;
+    }
 
-        ral::write_reg!(ral::usdhc, self.usdhc, CMD_ARG, cmd.mk_args());
-        ral::write_reg!(ral::usdhc, self.usdhc, CMD_XFR_TYP, cmd.mk_xfer());
+    /// Sends ACMD23 with the block count before every multi-block write, so
+    /// the card can erase the whole range upfront.
+    ///
+    /// Cards rejecting ACMD23 switch the option off again, see `pre_erase`.
+    pub fn set_pre_erase(&mut self, enable: bool) {
+        self.pre_erase = enable;
+    }
 
-        self.wait_for_command_complete();
-        self.wait_for_card();
+    /// True if multi-block writes are preceded by ACMD23
+    pub fn pre_erase(&self) -> bool {
+        self.pre_erase
+    }
 
-        let (cc, cie, cebe, cce, ctoe) =
-            ral::read_reg!(ral::usdhc, self.usdhc, INT_STATUS, CC, CIE, CEBE, CCE, CTOE);
-        ral::modify_reg!(
+    /// Programs the watermark levels and burst lengths (WTMK_LVL).
+    ///
+    /// The levels are the upper limit for every transfer, `prepare_transfer`
+    /// lowers the level of the transfer direction until it divides the
+    /// block, see `Watermark::level_for`.
+    pub fn set_watermark(&mut self, watermark: Watermark) {
+        self.watermark = watermark;
+        ral::write_reg!(
             ral::usdhc,
             self.usdhc,
-            INT_STATUS,
-            CC: cc,
-            CIE: cie,
-            CEBE: cebe,
-            CCE: cce,
-            CTOE: ctoe
+            WTMK_LVL,
+            RD_WML: watermark.read_level() as u32,
+            RD_BRST_LEN: watermark.read_burst() as u32,
+            WR_WML: watermark.write_level() as u32,
+            WR_BRST_LEN: watermark.write_burst() as u32
         );
-        return (cc == 1) && (cie + cebe + cce + ctoe == 0);
+    }
+
+    /// Configured watermark levels and burst lengths
+    pub fn watermark(&self) -> Watermark {
+        self.watermark
+    }
+
+    /// Hands an eDMA channel to the driver for `TransferMode::ExternalDma`,
+    /// returns the previously configured channel
+    pub fn set_external_dma(&mut self, edma: Option<ExternalDma>) -> Option<ExternalDma> {
+        core::mem::replace(&mut self.edma, edma)
     }
 
     pub fn read_response_u32(&self) -> u32 {
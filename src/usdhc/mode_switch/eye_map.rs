@@ -0,0 +1,107 @@
+//! # Eye map
+//!
+//! Result of the manual tuning sweep, kept free of the uSDHC so the window
+//! selection is tested on the host.
+
+/// Number of delay cells of DLY_CELL_SET_PRE
+pub const TUNING_TAPS: usize = 128;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+/// Pass/fail of every delay cell of a manual tuning sweep, bit `n` set if
+/// the tuning block was received correctly with `n` cells
+pub struct EyeMap(pub u128);
+
+impl EyeMap {
+    /// True if the tuning block passed with `tap` delay cells
+    pub fn passed(&self, tap: u8) -> bool {
+        tap < TUNING_TAPS as u8 && self.0 & (1 << tap) != 0
+    }
+
+    /// First tap and length of the widest passing window
+    pub fn widest_window(&self) -> Option<(u8, u8)> {
+        let mut best: Option<(u8, u8)> = None;
+        let mut start = 0;
+        for tap in 0..=TUNING_TAPS as u8 {
+            if tap < TUNING_TAPS as u8 && self.passed(tap) {
+                continue;
+            }
+            let len = tap - start;
+            if len > 0 && best.map(|(_, l)| len > l).unwrap_or(true) {
+                best = Some((start, len));
+            }
+            start = tap + 1;
+        }
+        best
+    }
+
+    /// Centre of the widest passing window
+    pub fn centre(&self) -> Option<u8> {
+        self.widest_window().map(|(start, len)| start + (len - 1) / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Eye map with the taps of `ranges` passing
+    fn passing(ranges: &[core::ops::Range<u8>]) -> EyeMap {
+        let mut eye = EyeMap::default();
+        for tap in ranges.iter().cloned().flatten() {
+            eye.0 |= 1 << tap;
+        }
+        eye
+    }
+
+    #[test]
+    fn all_taps_pass() {
+        let eye = EyeMap(u128::MAX);
+        assert_eq!(eye.widest_window(), Some((0, 128)));
+        assert_eq!(eye.centre(), Some(63));
+    }
+
+    #[test]
+    fn no_tap_passes() {
+        let eye = EyeMap::default();
+        assert_eq!(eye.widest_window(), None);
+        assert_eq!(eye.centre(), None);
+    }
+
+    #[test]
+    fn windows_at_the_edges() {
+        // the delay chain doesn't wrap, tap 127 and tap 0 are no window
+        let eye = passing(&[0..10, 120..128]);
+        assert_eq!(eye.widest_window(), Some((0, 10)));
+        assert_eq!(eye.centre(), Some(4));
+
+        let eye = passing(&[0..4, 100..128]);
+        assert_eq!(eye.widest_window(), Some((100, 28)));
+        assert_eq!(eye.centre(), Some(113));
+
+        assert_eq!(EyeMap(1 << 127).widest_window(), Some((127, 1)));
+        assert_eq!(EyeMap(1).widest_window(), Some((0, 1)));
+    }
+
+    #[test]
+    fn widest_of_several_windows() {
+        let eye = passing(&[5..10, 20..50, 60..71, 90..100]);
+        assert_eq!(eye.widest_window(), Some((20, 30)));
+        assert_eq!(eye.centre(), Some(34));
+    }
+
+    #[test]
+    fn first_of_equal_windows() {
+        let eye = passing(&[10..20, 40..50]);
+        assert_eq!(eye.widest_window(), Some((10, 10)));
+    }
+
+    #[test]
+    fn passed() {
+        let eye = EyeMap(0b11 << 3);
+        assert!(!eye.passed(2));
+        assert!(eye.passed(3));
+        assert!(eye.passed(4));
+        assert!(!eye.passed(5));
+        assert!(!EyeMap(u128::MAX).passed(TUNING_TAPS as u8));
+    }
+}
@@ -0,0 +1,125 @@
+//! # HS200
+//!
+//! The switch follows the sequence of the README: the EXT_CSD has to list
+//! HS200 at 1.8V, the bus has to be 4 or 8 bit wide and the pads have to
+//! run at 1.8V before HS_TIMING is written. SDCLK is raised afterwards,
+//! the switch is checked with CMD13 in the HS200 timing and the sampling
+//! point is tuned with CMD21.
+
+use teensy4_bsp::{
+    hal::{self, iomuxc, ral},
+    pins::imxrt_iomuxc::consts::Unsigned,
+    pins::imxrt_iomuxc::usdhc,
+};
+
+use super::{
+    super::{constants, registers::ExtendedCsd, BusWidth, CardMode, Error, USdhc},
+    TuningCommand,
+};
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Switches a selected and unlocked eMMC to HS200 (up to 200 MHz) and
+    /// tunes the sampling point with CMD21.
+    ///
+    /// The pads have to run at 1.8V (VEND_SPEC[VSELECT]), a 1 bit bus is
+    /// widened with `select_mmc_bus_width` first. Returns `Ok(false)` if
+    /// the device or the host can't do HS200. If the switch, the CMD13
+    /// check or the tuning fails, the device is set back to high speed
+    /// (52 MHz), or to the backwards compatible timing if it has no high
+    /// speed, and `Ok(false)` is returned as well.
+    pub fn switch_to_hs200(&mut self, ccm: &mut hal::ccm::Handle) -> Result<bool, Error> {
+        if self.locked {
+            return Err(Error::Locked);
+        }
+        let ext_csd = self.read_ext_csd()?;
+        if !ext_csd.supports_hs200_1v8() {
+            log::debug!("HS200 not supported, device type {:x}", ext_csd.device_type());
+            return Ok(false);
+        }
+        if !self.is_1v8_signaling() {
+            log::debug!("HS200 needs 1.8V signaling");
+            return Ok(false);
+        }
+        if self.bus_width() == BusWidth::One && self.select_mmc_bus_width()? == BusWidth::One {
+            log::debug!("HS200 needs a 4 or 8 bit bus");
+            return Ok(false);
+        }
+        // type 0 (50 Ohm) is mandatory
+        let driver_type = if ext_csd.supports_driver_type(self.mmc_driver_type) {
+            self.mmc_driver_type
+        } else {
+            0
+        };
+        log::debug!("driver types {:x}, using {}", ext_csd.driver_strength(), driver_type);
+
+        self.pins.set_pad_speed(iomuxc::Speed::Max, iomuxc::DriveStrength::R0_7);
+        let hs_timing = driver_type << 4 | ExtendedCsd::TIMING_HS200;
+        if let Err(err) = self.send_mmc_switch(ExtendedCsd::HS_TIMING, hs_timing) {
+            log::warn!("HS200 switch failed {:?}", err);
+            self.revert_to_mmc_high_speed(&ext_csd, ccm)?;
+            return Ok(false);
+        }
+
+        self.set_sd_clk(constants::SD_MAX_MMC_HS200_RATE_HZ, false, ccm);
+        self.mode = CardMode::MmcHs200;
+        log::debug!("HS200 at {} kHz", self.sd_clk_khz);
+
+        // CMD13 in the HS200 timing, the device answers with the new timing
+        if let Err(err) = self.check_mmc_switch() {
+            log::warn!("HS200 switch failed {:?}", err);
+            self.revert_to_mmc_high_speed(&ext_csd, ccm)?;
+            return Ok(false);
+        }
+
+        match self.execute_tuning(TuningCommand::Mmc) {
+            Ok(tap) => {
+                log::debug!("HS200 tuned, {} delay cells", tap);
+                Ok(true)
+            }
+            Err(err) => {
+                log::warn!("HS200 tuning failed {:?}", err);
+                self.revert_to_mmc_high_speed(&ext_csd, ccm)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Lowers SDCLK and writes the high speed timing (52 MHz) into
+    /// HS_TIMING, or the backwards compatible timing (26 MHz) if the device
+    /// doesn't support high speed
+    fn revert_to_mmc_high_speed(
+        &mut self,
+        ext_csd: &ExtendedCsd,
+        ccm: &mut hal::ccm::Handle,
+    ) -> Result<(), Error> {
+        let (timing, clock_hz, mode) = if ext_csd.supports_high_speed() {
+            (
+                ExtendedCsd::TIMING_HIGH_SPEED,
+                constants::SD_MAX_MMC_HIGH_SPEED_RATE_HZ,
+                CardMode::MmcHighSpeed,
+            )
+        } else {
+            (
+                ExtendedCsd::TIMING_BACKWARDS_COMPATIBLE,
+                constants::SD_MAX_MMC_FULL_SPEED_RATE_HZ,
+                CardMode::MmcFullSpeed,
+            )
+        };
+        ral::modify_reg!(ral::usdhc, self.usdhc, AUTOCMD12_ERR_STATUS, SMP_CLK_SEL: 0);
+        self.pins.set_pad_speed(iomuxc::Speed::Fast, iomuxc::DriveStrength::R0_7);
+        self.set_sd_clk(clock_hz, false, ccm);
+        self.reset_data_line()?;
+        self.mmc_switch(ExtendedCsd::HS_TIMING, timing)?;
+        self.mode = mode;
+        Ok(())
+    }
+}
@@ -1 +1,359 @@
+//! # Sampling point tuning
+//!
+//! SDR50, SDR104 and HS200 sample the data with a tuned clock, the delay
+//! chain is found with the standard tuning procedure described in the
+//! README: the uSDHC moves the sampling point after every tuning block
+//! (CMD19/CMD21) until it found a passing window.
+//!
+//! If the standard procedure can't close the passing window, the manual
+//! tuning sweeps all delay cells of DLY_CELL_SET_PRE, compares the tuning
+//! block against the known pattern and settles on the centre of the widest
+//! passing window. The pass/fail map of the sweep is kept as `EyeMap`.
+//!
+//! The HS200 switch of eMMC devices (`switch_to_hs200`) is in `hs200`.
+//!
+//! While tuning, the uSDHC only signals BRR, so the loop polls INT_STATUS
+//! with the USDHC1 interrupt masked.
 
+use teensy4_bsp::{
+    self as bsp,
+    hal::ral::{self, usdhc::INT_STATUS},
+    pins::imxrt_iomuxc::consts::Unsigned,
+    pins::imxrt_iomuxc::usdhc,
+};
+
+use super::{
+    commands::{self, SdCommand},
+    error::{COMMAND_ERRORS, DATA_ERRORS},
+    BusWidth, Error, USdhc, ARM_CLOCK_HZ,
+};
+
+mod eye_map;
+mod hs200;
+
+pub use eye_map::{EyeMap, TUNING_TAPS};
+
+/// Tuning blocks sent before the procedure is given up
+pub const TUNING_MAX_ATTEMPTS: u32 = 40;
+/// Time limit of the whole procedure
+pub const TUNING_TIMEOUT_MS: u32 = 150;
+
+/// First delay cell tried and cells added per tuning block
+const TUNING_START_TAP: u32 = 10;
+const TUNING_STEP: u32 = 2;
+
+/// Time a single tuning block may take in the manual sweep
+const TUNING_BLOCK_TIMEOUT_US: u32 = 1_000;
+/// Time the tuning circuit reset (RSTT) may take in the manual sweep
+const TUNING_RESET_TIMEOUT_US: u32 = 1_000;
+/// Poll interval of the waits for the uSDHC during the tuning
+const TUNING_POLL_US: u32 = 10;
+
+/// Tuning block on a 4 bit bus (CMD19, CMD21)
+const TUNING_PATTERN_4BIT: [u8; 64] = [
+    0xff, 0x0f, 0xff, 0x00, 0xff, 0xcc, 0xc3, 0xcc, 0xc3, 0x3c, 0xcc, 0xff, 0xfe, 0xff, 0xfe, 0xef,
+    0xff, 0xdf, 0xff, 0xdd, 0xff, 0xfb, 0xff, 0xfb, 0xbf, 0xff, 0x7f, 0xff, 0x77, 0xf7, 0xbd, 0xef,
+    0xff, 0xf0, 0xff, 0xf0, 0x0f, 0xfc, 0xcc, 0x3c, 0xcc, 0x33, 0xcc, 0xcf, 0xff, 0xef, 0xff, 0xee,
+    0xff, 0xfd, 0xff, 0xfd, 0xdf, 0xff, 0xbf, 0xff, 0xbb, 0xff, 0xf7, 0xff, 0xf7, 0x7f, 0x7b, 0xde,
+];
+
+/// Tuning block on an 8 bit bus (CMD21)
+const TUNING_PATTERN_8BIT: [u8; 128] = [
+    0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff, 0xcc, 0xcc, 0xcc, 0x33, 0xcc, 0xcc,
+    0xcc, 0x33, 0x33, 0xcc, 0xcc, 0xcc, 0xff, 0xff, 0xff, 0xee, 0xff, 0xff, 0xff, 0xee, 0xee, 0xff,
+    0xff, 0xff, 0xdd, 0xff, 0xff, 0xff, 0xdd, 0xdd, 0xff, 0xff, 0xff, 0xbb, 0xff, 0xff, 0xff, 0xbb,
+    0xbb, 0xff, 0xff, 0xff, 0x77, 0xff, 0xff, 0xff, 0x77, 0x77, 0xff, 0x77, 0xbb, 0xdd, 0xee, 0xff,
+    0xff, 0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff, 0xcc, 0xcc, 0xcc, 0x33, 0xcc,
+    0xcc, 0xcc, 0x33, 0x33, 0xcc, 0xcc, 0xcc, 0xff, 0xff, 0xff, 0xee, 0xff, 0xff, 0xff, 0xee, 0xee,
+    0xff, 0xff, 0xff, 0xdd, 0xff, 0xff, 0xff, 0xdd, 0xdd, 0xff, 0xff, 0xff, 0xbb, 0xff, 0xff, 0xff,
+    0xbb, 0xbb, 0xff, 0xff, 0xff, 0x77, 0xff, 0xff, 0xff, 0x77, 0x77, 0xff, 0x77, 0xbb, 0xdd, 0xee,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Command that requests the tuning block
+pub enum TuningCommand {
+    /// CMD19, SD SDR50/SDR104
+    Sd,
+    /// CMD21, eMMC HS200
+    Mmc,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Delay chain in use, CLK_TUNE_CTRL_STATUS TAP_SEL_* [30:16]. It's set by
+/// the standard tuning or, in the manual sweep, from DLY_CELL_SET_PRE.
+pub struct DelayChain {
+    /// TAP_SEL_PRE [30:24], delay cells before the sampling point
+    pub tap_sel_pre: u8,
+    /// TAP_SEL_OUT [23:20]
+    pub tap_sel_out: u8,
+    /// TAP_SEL_POST [19:16]
+    pub tap_sel_post: u8,
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Runs the standard tuning procedure at the current SDCLK and bus
+    /// width, returns the delay chain the uSDHC settled on.
+    ///
+    /// On failure the uSDHC falls back to the fixed sampling clock
+    /// (SMP_CLK_SEL = 0) and `Error::DataTimeout` is returned, or
+    /// `Error::Timeout` if the tuning circuit or the command/data lines
+    /// stayed busy.
+    pub fn execute_standard_tuning(&mut self, command: TuningCommand) -> Result<DelayChain, Error> {
+        cortex_m::peripheral::NVIC::mask(bsp::interrupt::USDHC1);
+        let result = self.standard_tuning(command);
+        if self.irq_enabled {
+            // Safety: the ISR state is unchanged, the interrupt was only masked
+            unsafe { cortex_m::peripheral::NVIC::unmask(bsp::interrupt::USDHC1) };
+        }
+        result
+    }
+
+    fn standard_tuning(&mut self, command: TuningCommand) -> Result<DelayChain, Error> {
+        let mut remaining_us = TUNING_TIMEOUT_MS * 1_000;
+        // 1. reset the tuning circuit
+        remaining_us = remaining_us.saturating_sub(self.reset_tuning(remaining_us)?);
+        // 2. - 4.
+        ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 1);
+        ral::modify_reg!(
+            ral::usdhc,
+            self.usdhc,
+            TUNING_CTRL,
+            DIS_CMD_CHK_FOR_STD_TUNING: 1,
+            TUNING_START_TAP: TUNING_START_TAP,
+            TUNING_STEP: TUNING_STEP,
+            STD_TUNING_EN: 1
+        );
+        ral::modify_reg!(ral::usdhc, self.usdhc, MIX_CTRL, EXE_TUNE: 1, AUTO_TUNE_EN: 0);
+
+        let mut block = [0u8; 128];
+        let block = &mut block[..self.tuning_block_size(command)];
+        let mut attempts = 0;
+
+        // 5. - 7.
+        let passed = loop {
+            if ral::read_reg!(ral::usdhc, self.usdhc, MIX_CTRL, EXE_TUNE == 0) {
+                break Ok(ral::read_reg!(ral::usdhc, self.usdhc, AUTOCMD12_ERR_STATUS, SMP_CLK_SEL == 1));
+            }
+            if attempts == TUNING_MAX_ATTEMPTS || remaining_us == 0 {
+                log::warn!("tuning gave up after {} blocks", attempts);
+                break Ok(false);
+            }
+            attempts += 1;
+
+            match self.send_tuning_block(command, block, remaining_us) {
+                Ok((_, elapsed_us)) => remaining_us = remaining_us.saturating_sub(elapsed_us),
+                Err(err) => break Err(err),
+            }
+        };
+
+        // 8. - 10.
+        if passed != Ok(true) {
+            ral::modify_reg!(ral::usdhc, self.usdhc, MIX_CTRL, EXE_TUNE: 0);
+            ral::modify_reg!(ral::usdhc, self.usdhc, AUTOCMD12_ERR_STATUS, SMP_CLK_SEL: 0);
+            ral::modify_reg!(ral::usdhc, self.usdhc, TUNING_CTRL, STD_TUNING_EN: 0);
+            self.reset_data_line()?;
+            return Err(passed.err().unwrap_or(Error::DataTimeout));
+        }
+        let chain = self.delay_chain();
+        ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 0);
+        ral::modify_reg!(ral::usdhc, self.usdhc, MIX_CTRL, AUTO_TUNE_EN: 1);
+        log::debug!("tuned after {} blocks {:?}", attempts, chain);
+        Ok(chain)
+    }
+
+    /// Runs the standard tuning and falls back to the manual sweep if it
+    /// fails, returns the delay cells before the sampling point: TAP_SEL_PRE
+    /// of the standard tuning or the DLY_CELL_SET_PRE the sweep selected
+    pub fn execute_tuning(&mut self, command: TuningCommand) -> Result<u8, Error> {
+        match self.execute_standard_tuning(command) {
+            Ok(chain) => Ok(chain.tap_sel_pre),
+            Err(err) => {
+                log::warn!("standard tuning failed {:?}, sweeping manually", err);
+                let eye = self.execute_manual_tuning(command)?;
+                eye.centre().ok_or(Error::DataTimeout)
+            }
+        }
+    }
+
+    /// Sweeps DLY_CELL_SET_PRE over all delay cells and selects the centre
+    /// of the widest window that received the tuning pattern correctly.
+    ///
+    /// Returns the eye map, it's also kept for diagnostics (`eye_map`). If
+    /// no tap passed, the fixed sampling clock is used again and
+    /// `Error::DataTimeout` is returned, `Error::Timeout` if the tuning
+    /// circuit or the command/data lines stayed busy.
+    pub fn execute_manual_tuning(&mut self, command: TuningCommand) -> Result<EyeMap, Error> {
+        cortex_m::peripheral::NVIC::mask(bsp::interrupt::USDHC1);
+        let result = self.manual_tuning(command);
+        if self.irq_enabled {
+            // Safety: the ISR state is unchanged, the interrupt was only masked
+            unsafe { cortex_m::peripheral::NVIC::unmask(bsp::interrupt::USDHC1) };
+        }
+        result
+    }
+
+    /// Delay chain the sampling clock currently uses
+    pub fn delay_chain(&self) -> DelayChain {
+        let (pre, out, post) = ral::read_reg!(
+            ral::usdhc,
+            self.usdhc,
+            CLK_TUNE_CTRL_STATUS,
+            TAP_SEL_PRE,
+            TAP_SEL_OUT,
+            TAP_SEL_POST
+        );
+        DelayChain {
+            tap_sel_pre: pre as u8,
+            tap_sel_out: out as u8,
+            tap_sel_post: post as u8,
+        }
+    }
+
+    /// Pass/fail map of the last manual tuning
+    pub fn eye_map(&self) -> Option<EyeMap> {
+        self.eye_map
+    }
+
+    fn manual_tuning(&mut self, command: TuningCommand) -> Result<EyeMap, Error> {
+        self.reset_tuning(TUNING_RESET_TIMEOUT_US)?;
+        ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 1);
+        ral::modify_reg!(ral::usdhc, self.usdhc, TUNING_CTRL, STD_TUNING_EN: 0);
+        ral::modify_reg!(ral::usdhc, self.usdhc, MIX_CTRL, AUTO_TUNE_EN: 0, FBCLK_SEL: 1);
+        ral::modify_reg!(ral::usdhc, self.usdhc, AUTOCMD12_ERR_STATUS, EXECUTE_TUNING: 1, SMP_CLK_SEL: 1);
+
+        let size = self.tuning_block_size(command);
+        let pattern: &[u8] = if size == 128 {
+            &TUNING_PATTERN_8BIT
+        } else {
+            &TUNING_PATTERN_4BIT
+        };
+        let mut block = [0u8; 128];
+        let mut eye = EyeMap::default();
+        let mut sweep = Ok(());
+        for tap in 0..TUNING_TAPS as u8 {
+            ral::modify_reg!(ral::usdhc, self.usdhc, CLK_TUNE_CTRL_STATUS, DLY_CELL_SET_PRE: tap as u32);
+            let received = match self.send_tuning_block(command, &mut block[..size], TUNING_BLOCK_TIMEOUT_US) {
+                Ok((received, _)) => received,
+                Err(err) => {
+                    sweep = Err(err);
+                    break;
+                }
+            };
+            if received && block[..size] == *pattern {
+                eye.0 |= 1 << tap;
+            }
+        }
+        self.eye_map = Some(eye);
+        log::debug!("eye map {:032x}", eye.0);
+
+        ral::modify_reg!(ral::usdhc, self.usdhc, AUTOCMD12_ERR_STATUS, EXECUTE_TUNING: 0);
+        let result = match sweep.and_then(|()| eye.centre().ok_or(Error::DataTimeout)) {
+            Ok(tap) => {
+                ral::modify_reg!(ral::usdhc, self.usdhc, CLK_TUNE_CTRL_STATUS, DLY_CELL_SET_PRE: tap as u32);
+                log::debug!("manual tuning selected tap {} {:?}", tap, eye.widest_window());
+                Ok(eye)
+            }
+            Err(err) => {
+                ral::modify_reg!(ral::usdhc, self.usdhc, AUTOCMD12_ERR_STATUS, SMP_CLK_SEL: 0);
+                Err(err)
+            }
+        };
+        ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 0);
+        result
+    }
+
+    /// Resets the tuning circuit (RSTT), returns the time it took. Fails with
+    /// `Error::Timeout` if RSTT doesn't clear within `timeout_us`.
+    fn reset_tuning(&mut self, timeout_us: u32) -> Result<u32, Error> {
+        ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTT: 1);
+        let mut elapsed_us = 0;
+        while ral::read_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTT == 1) {
+            if elapsed_us >= timeout_us {
+                log::error!("tuning circuit reset didn't complete");
+                return Err(Error::Timeout);
+            }
+            cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000_000 * TUNING_POLL_US);
+            elapsed_us += TUNING_POLL_US;
+        }
+        Ok(elapsed_us)
+    }
+
+    /// CMD21 sends 128 bytes on an 8 bit bus, every other tuning block is 64
+    fn tuning_block_size(&self, command: TuningCommand) -> usize {
+        match (command, self.bus_width()) {
+            (TuningCommand::Mmc, BusWidth::Eight) => 128,
+            _ => 64,
+        }
+    }
+
+    /// Requests one tuning block and reads it into `block`, returns whether
+    /// it arrived without error within `timeout_us` and the time spent.
+    /// Fails with `Error::Timeout` if the command/data lines stay inhibited
+    /// for `timeout_us`, the block isn't requested then.
+    fn send_tuning_block(
+        &mut self,
+        command: TuningCommand,
+        block: &mut [u8],
+        timeout_us: u32,
+    ) -> Result<(bool, u32), Error> {
+        let mut elapsed_us = 0;
+        let inhibit = ral::usdhc::PRES_STATE::CIHB::mask | ral::usdhc::PRES_STATE::CDIHB::mask;
+        while ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE) & inhibit != 0 {
+            if elapsed_us >= timeout_us {
+                log::error!("command/data line inhibited before the tuning block");
+                return Err(Error::Timeout);
+            }
+            cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000_000 * TUNING_POLL_US);
+            elapsed_us += TUNING_POLL_US;
+        }
+        ral::write_reg!(ral::usdhc, self.usdhc, BLK_ATT, BLKCNT: 1, BLKSIZE: block.len() as u32);
+        ral::modify_reg!(ral::usdhc, self.usdhc, MIX_CTRL, DTDSEL: 1, MSBSEL: 0, BCEN: 0, DMAEN: 0);
+        let (arg, xfer) = match command {
+            TuningCommand::Sd => {
+                let cmd = commands::SendTuningBlock64::new();
+                (cmd.mk_args(), cmd.mk_xfer())
+            }
+            TuningCommand::Mmc => {
+                let cmd = commands::SendTuningBlock128::new();
+                (cmd.mk_args(), cmd.mk_xfer())
+            }
+        };
+        ral::write_reg!(ral::usdhc, self.usdhc, CMD_ARG, arg);
+        ral::write_reg!(ral::usdhc, self.usdhc, CMD_XFR_TYP, xfer);
+
+        // a failing sampling point doesn't deliver the block at all
+        while elapsed_us < timeout_us && ral::read_reg!(ral::usdhc, self.usdhc, INT_STATUS, BRR == 0) {
+            cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000_000 * TUNING_POLL_US);
+            elapsed_us += TUNING_POLL_US;
+        }
+        let status = ral::read_reg!(ral::usdhc, self.usdhc, INT_STATUS);
+        let received = status & INT_STATUS::BRR::mask != 0;
+        if received {
+            for word in block.chunks_mut(4) {
+                let value = ral::read_reg!(ral::usdhc, self.usdhc, DATA_BUFF_ACC_PORT).to_le_bytes();
+                word.copy_from_slice(&value[..word.len()]);
+            }
+        }
+        ral::write_reg!(
+            ral::usdhc,
+            self.usdhc,
+            INT_STATUS,
+            INT_STATUS::BRR::mask | INT_STATUS::CC::mask | INT_STATUS::TC::mask | COMMAND_ERRORS | DATA_ERRORS
+        );
+        if status & (COMMAND_ERRORS | DATA_ERRORS) != 0 || !received {
+            // the block counts as failed either way
+            self.reset_data_line().ok();
+        }
+        cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000_000 * TUNING_POLL_US);
+        Ok((received && status & (COMMAND_ERRORS | DATA_ERRORS) == 0, elapsed_us + TUNING_POLL_US))
+    }
+}
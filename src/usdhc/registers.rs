@@ -0,0 +1,670 @@
+//! # Card registers
+//!
+//! Decoders for the registers and status words a card sends back.
+
+use super::{crc, Error};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// State of the card state machine, CURRENT_STATE [12:9] of the card status
+pub enum CurrentState {
+    Idle,
+    Ready,
+    Ident,
+    Stby,
+    Tran,
+    Data,
+    Rcv,
+    Prg,
+    Dis,
+    Reserved(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Card status, the 32 bit payload of an R1 response
+pub struct CardStatus(pub u32);
+
+impl CardStatus {
+    pub const OUT_OF_RANGE: u32 = 1 << 31;
+    pub const ADDRESS_ERROR: u32 = 1 << 30;
+    pub const BLOCK_LEN_ERROR: u32 = 1 << 29;
+    pub const ERASE_SEQ_ERROR: u32 = 1 << 28;
+    pub const ERASE_PARAM: u32 = 1 << 27;
+    pub const WP_VIOLATION: u32 = 1 << 26;
+    pub const CARD_IS_LOCKED: u32 = 1 << 25;
+    pub const LOCK_UNLOCK_FAILED: u32 = 1 << 24;
+    pub const COM_CRC_ERROR: u32 = 1 << 23;
+    pub const ILLEGAL_COMMAND: u32 = 1 << 22;
+    pub const CARD_ECC_FAILED: u32 = 1 << 21;
+    pub const CC_ERROR: u32 = 1 << 20;
+    pub const ERROR: u32 = 1 << 19;
+    pub const CSD_OVERWRITE: u32 = 1 << 16;
+    pub const WP_ERASE_SKIP: u32 = 1 << 15;
+    pub const CARD_ECC_DISABLED: u32 = 1 << 14;
+    pub const ERASE_RESET: u32 = 1 << 13;
+    pub const READY_FOR_DATA: u32 = 1 << 8;
+    /// eMMC: the last CMD6 SWITCH was rejected
+    pub const SWITCH_ERROR: u32 = 1 << 7;
+    pub const APP_CMD: u32 = 1 << 5;
+    pub const AKE_SEQ_ERROR: u32 = 1 << 3;
+
+    /// All bits which report an error of the previous or current command
+    pub const ERROR_MASK: u32 = Self::OUT_OF_RANGE
+        | Self::ADDRESS_ERROR
+        | Self::BLOCK_LEN_ERROR
+        | Self::ERASE_SEQ_ERROR
+        | Self::ERASE_PARAM
+        | Self::WP_VIOLATION
+        | Self::LOCK_UNLOCK_FAILED
+        | Self::COM_CRC_ERROR
+        | Self::ILLEGAL_COMMAND
+        | Self::CARD_ECC_FAILED
+        | Self::CC_ERROR
+        | Self::ERROR
+        | Self::CSD_OVERWRITE
+        | Self::WP_ERASE_SKIP
+        | Self::AKE_SEQ_ERROR;
+
+    pub fn current_state(&self) -> CurrentState {
+        match (self.0 >> 9) & 0xF {
+            0 => CurrentState::Idle,
+            1 => CurrentState::Ready,
+            2 => CurrentState::Ident,
+            3 => CurrentState::Stby,
+            4 => CurrentState::Tran,
+            5 => CurrentState::Data,
+            6 => CurrentState::Rcv,
+            7 => CurrentState::Prg,
+            8 => CurrentState::Dis,
+            s => CurrentState::Reserved(s as u8),
+        }
+    }
+
+    pub fn is_set(&self, flag: u32) -> bool {
+        (self.0 & flag) != 0
+    }
+
+    pub fn is_ready_for_data(&self) -> bool {
+        self.is_set(Self::READY_FOR_DATA)
+    }
+
+    /// `Err(Error::Card)` if any of the error bits is set, `Err(Error::Locked)`
+    /// if CMD42 failed (LOCK_UNLOCK_FAILED) or a locked card rejected the
+    /// command. CARD_IS_LOCKED alone isn't an error, it's set in every
+    /// response of a locked card.
+    pub fn check(self) -> Result<Self, Error> {
+        if self.0 & Self::ERROR_MASK == 0 {
+            Ok(self)
+        } else if self.is_set(Self::LOCK_UNLOCK_FAILED | Self::CARD_IS_LOCKED) {
+            Err(Error::Locked)
+        } else {
+            Err(Error::Card(self))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// State of an SDIO card, IO_CURRENT_STATE [13:12] of the R5 response
+pub enum IoState {
+    /// disabled, not selected
+    Dis,
+    /// selected, no data transfer in progress
+    Cmd,
+    /// data transfer in progress
+    Trn,
+    Reserved,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// R5 response of the SDIO commands CMD52/CMD53, flags [15:8] and data [7:0]
+pub struct IoStatus(pub u32);
+
+impl IoStatus {
+    pub const COM_CRC_ERROR: u32 = 1 << 15;
+    pub const ILLEGAL_COMMAND: u32 = 1 << 14;
+    pub const ERROR: u32 = 1 << 11;
+    pub const FUNCTION_NUMBER: u32 = 1 << 9;
+    pub const OUT_OF_RANGE: u32 = 1 << 8;
+
+    /// All bits which report an error of the command
+    pub const ERROR_MASK: u32 =
+        Self::COM_CRC_ERROR | Self::ILLEGAL_COMMAND | Self::ERROR | Self::FUNCTION_NUMBER | Self::OUT_OF_RANGE;
+
+    pub fn current_state(&self) -> IoState {
+        match (self.0 >> 12) & 0b11 {
+            0 => IoState::Dis,
+            1 => IoState::Cmd,
+            2 => IoState::Trn,
+            _ => IoState::Reserved,
+        }
+    }
+
+    /// register value read (or written) by CMD52
+    pub fn data(&self) -> u8 {
+        self.0 as u8
+    }
+
+    pub fn is_set(&self, flag: u32) -> bool {
+        (self.0 & flag) != 0
+    }
+
+    /// `Err(Error::Io)` if any of the error bits is set
+    pub fn check(self) -> Result<Self, Error> {
+        if self.0 & Self::ERROR_MASK != 0 {
+            Err(Error::Io(self))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+/// Operation conditions register, R3 response of ACMD41
+pub struct Ocr(pub u32);
+
+impl Ocr {
+    /// Card power up status, initialization finished
+    pub const BUSY: u32 = 1 << 31;
+    /// Card capacity status (HCS in the ACMD41 argument)
+    pub const CCS: u32 = 1 << 30;
+    /// Switching to 1.8V accepted (S18R in the ACMD41 argument)
+    pub const S18A: u32 = 1 << 24;
+    /// 3.2-3.4V window
+    pub const VDD_32_34: u32 = 0b11 << 20;
+
+    pub fn is_ready(&self) -> bool {
+        self.0 & Self::BUSY != 0
+    }
+
+    pub fn high_capacity(&self) -> bool {
+        self.0 & Self::CCS != 0
+    }
+
+    pub fn accepts_1v8(&self) -> bool {
+        self.0 & Self::S18A != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+/// SD Configuration Register (SCR), 64 bit read with ACMD51
+pub struct Scr(pub u64);
+
+impl Scr {
+    /// The SCR is sent MSB first, like any other data block
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_be_bytes(bytes))
+    }
+
+    fn bits(&self, high: u32, low: u32) -> u32 {
+        ((self.0 >> low) & ((1u64 << (high - low + 1)) - 1)) as u32
+    }
+
+    /// SCR_STRUCTURE [63:60]
+    pub fn structure(&self) -> u8 {
+        self.bits(63, 60) as u8
+    }
+
+    /// SD_SPEC [59:56]
+    pub fn sd_spec(&self) -> u8 {
+        self.bits(59, 56) as u8
+    }
+
+    /// DATA_STAT_AFTER_ERASE [55]
+    pub fn data_stat_after_erase(&self) -> bool {
+        self.bits(55, 55) == 1
+    }
+
+    /// SD_SECURITY [54:52]
+    pub fn sd_security(&self) -> u8 {
+        self.bits(54, 52) as u8
+    }
+
+    /// SD_BUS_WIDTHS [51:48], bit 0 = 1 bit, bit 2 = 4 bit
+    pub fn bus_widths(&self) -> u8 {
+        self.bits(51, 48) as u8
+    }
+
+    pub fn supports_4bit(&self) -> bool {
+        self.bus_widths() & 0b0100 != 0
+    }
+
+    /// SD_SPEC3 [47]
+    pub fn sd_spec3(&self) -> bool {
+        self.bits(47, 47) == 1
+    }
+
+    /// SD_SPEC4 [42]
+    pub fn sd_spec4(&self) -> bool {
+        self.bits(42, 42) == 1
+    }
+
+    /// SD_SPECX [41:38]
+    pub fn sd_specx(&self) -> u8 {
+        self.bits(41, 38) as u8
+    }
+
+    /// CMD_SUPPORT [35:32]
+    pub fn cmd_support(&self) -> u8 {
+        self.bits(35, 32) as u8
+    }
+
+    /// SET_BLOCK_COUNT (CMD23) is supported
+    pub fn supports_cmd23(&self) -> bool {
+        self.cmd_support() & 0b0010 != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+/// Card Specific Data (CSD), 128 bit R2 response of CMD9
+pub struct Csd(pub u128);
+
+impl Csd {
+    /// The uSDHC stores the R2 payload without the CRC, shifted right by 8
+    /// bits, in CMD_RSP0..=CMD_RSP3
+    pub fn from_response(rsp: [u32; 4]) -> Self {
+        let value = (rsp[3] as u128) << 96 | (rsp[2] as u128) << 64 | (rsp[1] as u128) << 32 | rsp[0] as u128;
+        Self(value << 8)
+    }
+
+    fn bits(&self, high: u32, low: u32) -> u32 {
+        ((self.0 >> low) & ((1u128 << (high - low + 1)) - 1)) as u32
+    }
+
+    /// CSD_STRUCTURE [127:126], 0 = SDSC (1.0), 1 = SDHC/SDXC (2.0), 2 = SDUC (3.0)
+    pub fn structure(&self) -> u8 {
+        self.bits(127, 126) as u8
+    }
+
+    /// TRAN_SPEED [103:96]
+    pub fn tran_speed(&self) -> u8 {
+        self.bits(103, 96) as u8
+    }
+
+    /// CCC [95:84], supported command classes
+    pub fn command_classes(&self) -> u16 {
+        self.bits(95, 84) as u16
+    }
+
+    /// ERASE_BLK_EN [46], erase of single write blocks is supported
+    pub fn erase_blk_en(&self) -> bool {
+        self.bits(46, 46) == 1
+    }
+
+    /// SECTOR_SIZE [45:39] + 1, erase unit in write blocks if ERASE_BLK_EN is 0
+    pub fn sector_size(&self) -> u32 {
+        self.bits(45, 39) + 1
+    }
+
+    /// WP_GRP_SIZE [38:32] + 1, write protect group in erase sectors
+    pub fn wp_grp_size(&self) -> u32 {
+        self.bits(38, 32) + 1
+    }
+
+    /// WP_GRP_ENABLE [31], group write protection is supported (SDSC only)
+    pub fn wp_grp_enable(&self) -> bool {
+        self.bits(31, 31) == 1
+    }
+
+    /// PERM_WRITE_PROTECT [13]
+    pub fn perm_write_protect(&self) -> bool {
+        self.bits(13, 13) == 1
+    }
+
+    /// TMP_WRITE_PROTECT [12]
+    pub fn tmp_write_protect(&self) -> bool {
+        self.bits(12, 12) == 1
+    }
+
+    /// Copy with TMP_WRITE_PROTECT changed
+    pub fn with_tmp_write_protect(self, protect: bool) -> Self {
+        Self(self.0 & !(1 << 12) | (protect as u128) << 12)
+    }
+
+    /// Copy with PERM_WRITE_PROTECT set
+    pub fn with_perm_write_protect(self) -> Self {
+        Self(self.0 | 1 << 13)
+    }
+
+    /// The 16 byte data block of CMD27, MSB first with CRC7 and end bit
+    pub fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = self.0.to_be_bytes();
+        bytes[15] = crc::crc7(&bytes, 15);
+        bytes
+    }
+
+    /// Number of write blocks in the erase range `start_lba..=end_lba`,
+    /// `Error::InvalidArgument` if it's reversed, ends beyond the capacity
+    /// or doesn't fit the count
+    pub fn erase_range(&self, start_lba: u32, end_lba: u32) -> Result<u32, Error> {
+        if start_lba > end_lba || end_lba as u64 >= self.block_count() {
+            return Err(Error::InvalidArgument);
+        }
+        (end_lba - start_lba).checked_add(1).ok_or(Error::InvalidArgument)
+    }
+
+    /// Capacity in 512 byte blocks
+    pub fn block_count(&self) -> u64 {
+        match self.structure() {
+            0 => {
+                let c_size = self.bits(73, 62) as u64;
+                let c_size_mult = self.bits(49, 47);
+                let read_bl_len = self.bits(83, 80);
+                ((c_size + 1) << (c_size_mult + 2)) << read_bl_len >> 9
+            }
+            1 => (self.bits(69, 48) as u64 + 1) * 1024,
+            _ => (self.bits(75, 48) as u64 + 1) * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// SD Status, 512 bit data block of ACMD13
+pub struct SdStatus(pub [u8; 64]);
+
+impl SdStatus {
+    /// Bit `high..=low` of the status, bit 511 is the MSB of the first byte
+    fn bits(&self, high: u32, low: u32) -> u32 {
+        (low..=high).rev().fold(0, |value, bit| {
+            let byte = self.0[(511 - bit) as usize / 8];
+            (value << 1) | ((byte >> (bit % 8)) & 1) as u32
+        })
+    }
+
+    /// DAT_BUS_WIDTH [511:510], 0 = 1 bit, 2 = 4 bit
+    pub fn bus_width(&self) -> u8 {
+        self.bits(511, 510) as u8
+    }
+
+    /// SPEED_CLASS [447:440]
+    pub fn speed_class(&self) -> u8 {
+        self.bits(447, 440) as u8
+    }
+
+    /// AU_SIZE [431:428]
+    pub fn au_size(&self) -> u8 {
+        self.bits(431, 428) as u8
+    }
+
+    /// Allocation unit in 512 byte blocks, 0 if not defined
+    pub fn au_blocks(&self) -> u32 {
+        const AU_KIB: [u32; 16] = [
+            0, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 12288, 16384, 24576, 32768, 65536,
+        ];
+        AU_KIB[self.au_size() as usize] * 2
+    }
+
+    /// ERASE_SIZE [423:408], number of AUs the timeout below refers to
+    pub fn erase_size(&self) -> u16 {
+        self.bits(423, 408) as u16
+    }
+
+    /// ERASE_TIMEOUT [407:402], seconds to erase `erase_size` AUs
+    pub fn erase_timeout(&self) -> u8 {
+        self.bits(407, 402) as u8
+    }
+
+    /// ERASE_OFFSET [401:400], fixed seconds added to every erase
+    pub fn erase_offset(&self) -> u8 {
+        self.bits(401, 400) as u8
+    }
+
+    /// DISCARD_SUPPORT [313]
+    pub fn discard_support(&self) -> bool {
+        self.bits(313, 313) == 1
+    }
+
+    /// FULE_SUPPORT [312]
+    pub fn fule_support(&self) -> bool {
+        self.bits(312, 312) == 1
+    }
+
+    /// Busy timeout in ms for erasing `blocks` write blocks.
+    ///
+    /// T_ERASE / N_ERASE * N_AU + T_OFFSET, at least 1 s. Without the
+    /// erase fields 250 ms per AU (or per block if AU_SIZE is undefined).
+    pub fn erase_timeout_ms(&self, blocks: u32) -> u32 {
+        let au = self.au_blocks();
+        let units = if au == 0 { blocks } else { blocks.div_ceil(au) };
+        if self.erase_size() == 0 || self.erase_timeout() == 0 {
+            return units.saturating_mul(250).max(1000);
+        }
+        let per_unit = self.erase_timeout() as u32 * 1000 / self.erase_size() as u32;
+        units
+            .saturating_mul(per_unit.max(1))
+            .saturating_add(self.erase_offset() as u32 * 1000)
+            .max(1000)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Switch function status, 512 bit data block of CMD6
+pub struct SwitchStatus(pub [u8; 64]);
+
+impl SwitchStatus {
+    /// Result of the function group if the switch failed or the function
+    /// is busy
+    pub const INVALID_FUNCTION: u8 = 0xF;
+
+    /// Bit `high..=low` of the status, bit 511 is the MSB of the first byte
+    fn bits(&self, high: u32, low: u32) -> u32 {
+        (low..=high).rev().fold(0, |value, bit| {
+            let byte = self.0[(511 - bit) as usize / 8];
+            (value << 1) | ((byte >> (bit % 8)) & 1) as u32
+        })
+    }
+
+    /// Maximum current consumption [511:496] in mA, 0 on error
+    pub fn max_current_ma(&self) -> u16 {
+        self.bits(511, 496) as u16
+    }
+
+    /// Support bits of function group `group` (1..=6), [415:400] for group 1
+    pub fn supported(&self, group: u8) -> u16 {
+        let low = 400 + 16 * (group as u32 - 1);
+        self.bits(low + 15, low) as u16
+    }
+
+    pub fn supports(&self, group: u8, function: u8) -> bool {
+        self.supported(group) & (1 << function) != 0
+    }
+
+    /// Function selected in group `group` (1..=6), [379:376] for group 1,
+    /// `INVALID_FUNCTION` if it can't be switched
+    pub fn selected(&self, group: u8) -> u8 {
+        let low = 376 + 4 * (group as u32 - 1);
+        self.bits(low + 3, low) as u8
+    }
+
+    /// Data structure version [375:368], the busy status is valid from 1 on
+    pub fn version(&self) -> u8 {
+        self.bits(375, 368) as u8
+    }
+
+    /// Busy status of the functions of group `group` [287:272] for group 1
+    pub fn busy(&self, group: u8) -> u16 {
+        let low = 272 + 16 * (group as u32 - 1);
+        self.bits(low + 15, low) as u16
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// eMMC Extended CSD register, 512 bytes read with CMD8
+pub struct ExtendedCsd(pub [u8; 512]);
+
+impl ExtendedCsd {
+    /// HS_TIMING [185]
+    pub const HS_TIMING: u8 = 185;
+    /// DEVICE_TYPE [196]
+    pub const DEVICE_TYPE: u8 = 196;
+    /// DRIVER_STRENGTH [197]
+    pub const DRIVER_STRENGTH: u8 = 197;
+
+    /// DEVICE_TYPE bits
+    pub const HS_26MHZ: u8 = 1 << 0;
+    pub const HS_52MHZ: u8 = 1 << 1;
+    pub const DDR_52MHZ_1V8_3V: u8 = 1 << 2;
+    pub const DDR_52MHZ_1V2: u8 = 1 << 3;
+    pub const HS200_1V8: u8 = 1 << 4;
+    pub const HS200_1V2: u8 = 1 << 5;
+
+    /// HS_TIMING timing interface values, [3:0]
+    pub const TIMING_BACKWARDS_COMPATIBLE: u8 = 0;
+    pub const TIMING_HIGH_SPEED: u8 = 1;
+    pub const TIMING_HS200: u8 = 2;
+
+    /// HS_TIMING [185], driver strength [7:4] and timing interface [3:0]
+    pub fn hs_timing(&self) -> u8 {
+        self.0[Self::HS_TIMING as usize]
+    }
+
+    /// DEVICE_TYPE [196]
+    pub fn device_type(&self) -> u8 {
+        self.0[Self::DEVICE_TYPE as usize]
+    }
+
+    /// Supported driver types [197], bit `n` for type `n`, type 0 (50 Ohm)
+    /// is mandatory
+    pub fn driver_strength(&self) -> u8 {
+        self.0[Self::DRIVER_STRENGTH as usize]
+    }
+
+    pub fn supports_driver_type(&self, driver_type: u8) -> bool {
+        driver_type == 0 || (driver_type < 8 && self.driver_strength() & (1 << driver_type) != 0)
+    }
+
+    pub fn supports_high_speed(&self) -> bool {
+        self.device_type() & Self::HS_52MHZ != 0
+    }
+
+    pub fn supports_hs200_1v8(&self) -> bool {
+        self.device_type() & Self::HS200_1V8 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn card_status_without_errors_passes() {
+        let status = CardStatus(CardStatus::READY_FOR_DATA | 4 << 9);
+        assert_eq!(status.check(), Ok(status));
+        // a locked card reports CARD_IS_LOCKED in every response
+        let locked = CardStatus(status.0 | CardStatus::CARD_IS_LOCKED);
+        assert_eq!(locked.check(), Ok(locked));
+    }
+
+    #[test]
+    fn card_status_errors_are_reported() {
+        let status = CardStatus(CardStatus::ADDRESS_ERROR | 4 << 9);
+        assert_eq!(status.check(), Err(Error::Card(status)));
+    }
+
+    #[test]
+    fn lock_errors_map_to_locked() {
+        // wrong password or CMD42 on a card without one
+        assert_eq!(CardStatus(CardStatus::LOCK_UNLOCK_FAILED).check(), Err(Error::Locked));
+        assert_eq!(
+            CardStatus(CardStatus::LOCK_UNLOCK_FAILED | CardStatus::CARD_IS_LOCKED).check(),
+            Err(Error::Locked)
+        );
+        // block command sent to a locked card
+        assert_eq!(
+            CardStatus(CardStatus::ILLEGAL_COMMAND | CardStatus::CARD_IS_LOCKED).check(),
+            Err(Error::Locked)
+        );
+    }
+
+    /// SDHC/SDXC CSD (structure 1) with `c_size`, (c_size + 1) * 1024 blocks
+    fn csd_v2(c_size: u32) -> Csd {
+        Csd(1 << 126 | (c_size as u128) << 48)
+    }
+
+    /// SD Status with the AU and erase fields set, the rest 0
+    fn sd_status(au_size: u8, erase_size: u16, erase_timeout: u8, erase_offset: u8) -> SdStatus {
+        let mut status = SdStatus([0; 64]);
+        let mut set = |high: u32, low: u32, value: u32| {
+            for bit in low..=high {
+                if value >> (bit - low) & 1 != 0 {
+                    status.0[(511 - bit) as usize / 8] |= 1 << (bit % 8);
+                }
+            }
+        };
+        set(431, 428, au_size as u32);
+        set(423, 408, erase_size as u32);
+        set(407, 402, erase_timeout as u32);
+        set(401, 400, erase_offset as u32);
+        status
+    }
+
+    #[test]
+    fn sd_status_fields_are_decoded() {
+        let status = sd_status(9, 0x1234, 0x2A, 3);
+        assert_eq!(status.au_size(), 9);
+        assert_eq!(status.au_blocks(), 8192);
+        assert_eq!(status.erase_size(), 0x1234);
+        assert_eq!(status.erase_timeout(), 0x2A);
+        assert_eq!(status.erase_offset(), 3);
+    }
+
+    #[test]
+    fn erase_timeout_from_the_erase_fields() {
+        // 4 MiB AU, 2 s for 4 AUs plus 1 s offset: 500 ms per AU
+        let status = sd_status(9, 4, 2, 1);
+        assert_eq!(status.erase_timeout_ms(8 * 8192), 8 * 500 + 1000);
+        // a partial AU counts as a whole one
+        assert_eq!(status.erase_timeout_ms(8 * 8192 + 1), 9 * 500 + 1000);
+        // never below 1 s
+        assert_eq!(sd_status(9, 4, 2, 0).erase_timeout_ms(1), 1000);
+    }
+
+    #[test]
+    fn erase_timeout_falls_back_without_erase_size() {
+        // ERASE_SIZE 0: 250 ms per AU, ERASE_TIMEOUT is ignored
+        let status = sd_status(9, 0, 10, 1);
+        assert_eq!(status.erase_timeout_ms(16 * 8192), 16 * 250);
+        assert_eq!(status.erase_timeout_ms(8192), 1000);
+        // ERASE_TIMEOUT 0 is handled the same way
+        assert_eq!(sd_status(9, 4, 0, 0).erase_timeout_ms(16 * 8192), 16 * 250);
+    }
+
+    #[test]
+    fn erase_timeout_falls_back_to_blocks_without_au_size() {
+        let status = sd_status(0, 0, 0, 0);
+        assert_eq!(status.au_blocks(), 0);
+        assert_eq!(status.erase_timeout_ms(3), 1000);
+        assert_eq!(status.erase_timeout_ms(10), 2500);
+    }
+
+    #[test]
+    fn erase_timeout_saturates_for_the_maximal_range() {
+        assert_eq!(sd_status(0, 0, 0, 0).erase_timeout_ms(u32::MAX), u32::MAX);
+        // 16 KiB AU, 63 s per AU plus the offset
+        assert_eq!(sd_status(1, 1, 63, 3).erase_timeout_ms(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn erase_range_counts_inclusive_blocks() {
+        let csd = csd_v2(1023);
+        assert_eq!(csd.block_count(), 1024 * 1024);
+        assert_eq!(csd.erase_range(0, 0), Ok(1));
+        assert_eq!(csd.erase_range(8, 15), Ok(8));
+        assert_eq!(csd.erase_range(0, 1024 * 1024 - 1), Ok(1024 * 1024));
+    }
+
+    #[test]
+    fn erase_range_rejects_reversed_and_out_of_range() {
+        let csd = csd_v2(1023);
+        assert_eq!(csd.erase_range(16, 15), Err(Error::InvalidArgument));
+        assert_eq!(csd.erase_range(0, 1024 * 1024), Err(Error::InvalidArgument));
+        assert_eq!(csd.erase_range(u32::MAX, u32::MAX), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn erase_range_rejects_the_maximal_range() {
+        // 2 TiB SDXC, every LBA is on the card but the count overflows
+        let csd = csd_v2((1 << 22) - 1);
+        assert_eq!(csd.block_count(), 1 << 32);
+        assert_eq!(csd.erase_range(1, u32::MAX), Ok(u32::MAX));
+        assert_eq!(csd.erase_range(0, u32::MAX), Err(Error::InvalidArgument));
+    }
+}
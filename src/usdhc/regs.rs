@@ -0,0 +1,77 @@
+//! # Register backend
+//!
+//! The command engine (`engine.rs`) reaches the uSDHC through `Registers`,
+//! so the same code runs on the hardware (`transfer::Hardware`, imxrt-ral)
+//! and against the simulated uSDHC of the host tests (`sim.rs`).
+//!
+//! Only the registers of the command path are part of the backend, the
+//! bits are given as plain masks so the engine doesn't depend on the RAL.
+
+use core::task::{Context, Poll};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Registers used by the command engine
+pub enum Register {
+    CmdArg,
+    CmdXfrTyp,
+    CmdRsp0,
+    PresState,
+    IntStatus,
+    AutoCmd12ErrStatus,
+}
+
+/// INT_STATUS bits
+pub mod int_status {
+    pub const CC: u32 = 1 << 0;
+    pub const TC: u32 = 1 << 1;
+    pub const DINT: u32 = 1 << 3;
+    pub const BWR: u32 = 1 << 4;
+    pub const BRR: u32 = 1 << 5;
+    pub const CTOE: u32 = 1 << 16;
+    pub const CCE: u32 = 1 << 17;
+    pub const CEBE: u32 = 1 << 18;
+    pub const CIE: u32 = 1 << 19;
+    pub const DTOE: u32 = 1 << 20;
+    pub const DCE: u32 = 1 << 21;
+    pub const DEBE: u32 = 1 << 22;
+    pub const AC12E: u32 = 1 << 24;
+    pub const DMAE: u32 = 1 << 28;
+}
+
+/// PRES_STATE bits
+pub mod pres_state {
+    /// command inhibit (CMD)
+    pub const CIHB: u32 = 1 << 0;
+    /// command inhibit (DATA)
+    pub const CDIHB: u32 = 1 << 1;
+    /// data line active
+    pub const DLA: u32 = 1 << 2;
+    /// DAT0 level (DLSL[0]), low while the card is busy
+    pub const DAT0: u32 = 1 << 24;
+}
+
+/// Access to the uSDHC registers of the command path
+pub trait Registers {
+    fn read(&self, register: Register) -> u32;
+
+    /// INT_STATUS is write 1 to clear, like on the hardware
+    fn write(&mut self, register: Register, value: u32);
+
+    /// Free running microsecond clock the timeouts are measured with, it
+    /// may wrap
+    fn now_us(&mut self) -> u32;
+
+    /// Ready with INT_STATUS once one of the `mask` bits is set.
+    ///
+    /// The default reads the register and stays runnable, the hardware
+    /// backend waits for the ISR while interrupts are enabled.
+    fn poll_int_status(&mut self, mask: u32, cx: &mut Context<'_>) -> Poll<u32> {
+        let status = self.read(Register::IntStatus);
+        if status & mask != 0 {
+            Poll::Ready(status)
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
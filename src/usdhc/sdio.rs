@@ -0,0 +1,197 @@
+//! # SDIO
+//!
+//! I/O register access of SDIO cards and combo cards.
+//!
+//! ## Card interrupts
+//!
+//! A function signals an interrupt by pulling DAT1 low. The ISR masks CINT,
+//! reads INT_PENDING from the CCCR and calls the handlers of the pending
+//! functions (`irq.rs`). The handler runs in interrupt context, or in the
+//! task that releases the bus if the interrupt arrived during a command,
+//! with interrupts enabled either way. It should only notify a task, which
+//! then clears the interrupt source in the function with CMD52/CMD53 and
+//! calls `rearm_io_interrupt`.
+//!
+//! In 4-bit mode DAT1 is also a data line, the card can only signal the
+//! interrupt in the interrupt period between two blocks of a multi-block
+//! transfer if both sides enable it (CCCR E4MI, PROT_CTRL[IABG]).
+
+use teensy4_bsp::{hal::ral, pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    block_transfer::{DataTransfer, IoAddressMode, IO_MAX_BLOCK_SIZE},
+    cis, commands, irq,
+    registers::IoStatus,
+    Error, USdhc,
+};
+
+/// CCCR/FBR offset of the 16 bit I/O block size
+const FBR_BLOCK_SIZE: u32 = 0x10;
+/// CCCR Int Enable, bit 0 IENM (master), bit n IEN of function n
+const CCCR_INT_ENABLE: u32 = 0x04;
+/// CCCR Card Capability, S4MI [4] and E4MI [5]
+const CCCR_CARD_CAPABILITY: u32 = 0x08;
+const S4MI: u8 = 1 << 4;
+const E4MI: u8 = 1 << 5;
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Reads a register of `function` with CMD52
+    pub fn io_read_byte(&mut self, function: u8, address: u32) -> Result<u8, Error> {
+        if function > 7 || address > 0x1_FFFF {
+            return Err(Error::InvalidArgument);
+        }
+        let status = self.execute(commands::IoRwDirect::read(function, address))?;
+        Ok(IoStatus(status).check()?.data())
+    }
+
+    /// Writes a register of `function` with CMD52.
+    ///
+    /// Returns the register value read back after the write if
+    /// `read_after_write` is set, the written value otherwise.
+    pub fn io_write_byte(
+        &mut self,
+        function: u8,
+        address: u32,
+        value: u8,
+        read_after_write: bool,
+    ) -> Result<u8, Error> {
+        if function > 7 || address > 0x1_FFFF {
+            return Err(Error::InvalidArgument);
+        }
+        let status = self.execute(commands::IoRwDirect::write(function, address, value, read_after_write))?;
+        Ok(IoStatus(status).check()?.data())
+    }
+
+    /// Sets the block size used for block mode CMD53 of `function`, 0 (the
+    /// default) restricts the function to byte mode.
+    ///
+    /// Written into the I/O block size of the FBR (CCCR for function 0).
+    pub fn set_io_block_size(&mut self, function: u8, block_size: u16) -> Result<(), Error> {
+        if function > 7 || block_size as usize > IO_MAX_BLOCK_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let base = (function as u32) << 8;
+        let [low, high] = block_size.to_le_bytes();
+        self.io_write_byte(0, base + FBR_BLOCK_SIZE, low, false)?;
+        self.io_write_byte(0, base + FBR_BLOCK_SIZE + 1, high, false)?;
+        self.io_block_size[function as usize] = block_size;
+        Ok(())
+    }
+
+    /// Block size of `function`, see `set_io_block_size`
+    pub fn io_block_size(&self, function: u8) -> u16 {
+        self.io_block_size.get(function as usize).copied().unwrap_or(0)
+    }
+
+    /// Reads `data.len()` bytes from the register space of `function` with CMD53
+    pub fn io_read_extended(
+        &mut self,
+        function: u8,
+        address: u32,
+        mode: IoAddressMode,
+        data: &mut [u8],
+    ) -> Result<(), Error> {
+        let block_size = self.io_block_size(function) as usize;
+        DataTransfer::read(function, address, mode, data, block_size).send_data(self)
+    }
+
+    /// Writes `data` into the register space of `function` with CMD53
+    pub fn io_write_extended(
+        &mut self,
+        function: u8,
+        address: u32,
+        mode: IoAddressMode,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let block_size = self.io_block_size(function) as usize;
+        DataTransfer::write(function, address, mode, data, block_size).send_data(self)
+    }
+
+    /// Registers the interrupt handler of `function` (1..=7), see the module
+    /// documentation for the context it runs in
+    pub fn set_io_interrupt_handler(&mut self, function: u8, handler: Option<fn()>) -> Result<(), Error> {
+        if !(1..=7).contains(&function) {
+            return Err(Error::InvalidArgument);
+        }
+        irq::set_io_handler(function, handler);
+        Ok(())
+    }
+
+    /// Enables the interrupt of `function` in the CCCR (IENx and IENM) and
+    /// the card interrupt of the uSDHC.
+    ///
+    /// Requires the interrupt driven engine (`enable_interrupts`). On a 4-bit
+    /// bus the interrupt period is enabled if the card supports it.
+    pub fn enable_io_interrupt(&mut self, function: u8) -> Result<(), Error> {
+        if !(1..=7).contains(&function) || !self.irq_enabled {
+            return Err(Error::InvalidArgument);
+        }
+        let enable = self.io_read_byte(0, CCCR_INT_ENABLE)?;
+        self.io_write_byte(0, CCCR_INT_ENABLE, enable | 1 | (1 << function), false)?;
+
+        if ral::read_reg!(ral::usdhc, self.usdhc, PROT_CTRL, DTW) == 0b01 {
+            let capability = self.io_read_byte(0, CCCR_CARD_CAPABILITY)?;
+            if capability & S4MI != 0 {
+                self.io_write_byte(0, CCCR_CARD_CAPABILITY, capability | E4MI, false)?;
+                ral::modify_reg!(ral::usdhc, self.usdhc, PROT_CTRL, IABG: 1);
+            }
+        }
+
+        irq::rearm_io_interrupt();
+        Ok(())
+    }
+
+    /// Disables the interrupt of `function`, the master enable and the card
+    /// interrupt of the uSDHC are turned off with the last function
+    pub fn disable_io_interrupt(&mut self, function: u8) -> Result<(), Error> {
+        if !(1..=7).contains(&function) {
+            return Err(Error::InvalidArgument);
+        }
+        let enable = self.io_read_byte(0, CCCR_INT_ENABLE)? & !(1 << function);
+        if enable & 0xFE == 0 {
+            ral::modify_reg!(ral::usdhc, self.usdhc, INT_SIGNAL_EN, CINTIEN: 0);
+            ral::modify_reg!(ral::usdhc, self.usdhc, INT_STATUS_EN, CINTSEN: 0);
+            ral::modify_reg!(ral::usdhc, self.usdhc, PROT_CTRL, IABG: 0);
+            self.io_write_byte(0, CCCR_INT_ENABLE, 0, false)?;
+        } else {
+            self.io_write_byte(0, CCCR_INT_ENABLE, enable, false)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the CIS chain of `function` (0 = common CIS) into `buffer` with
+    /// CMD52, returns the number of bytes used, decode them with `Cis::new`.
+    ///
+    /// A chain that doesn't fit is cut at the last complete tuple.
+    pub fn read_cis(&mut self, function: u8, buffer: &mut [u8]) -> Result<usize, Error> {
+        if function > 7 {
+            return Err(Error::InvalidArgument);
+        }
+        let base = ((function as u32) << 8) + cis::CIS_POINTER;
+        let mut address = 0u32;
+        for i in 0..3 {
+            address |= (self.io_read_byte(0, base + i)? as u32) << (8 * i);
+        }
+        cis::read_chain(address, buffer, |address| self.io_read_byte(0, address))
+    }
+
+    /// Functions that signaled an interrupt since the previous call, bit n = function n
+    pub fn io_interrupt_pending(&mut self) -> u8 {
+        irq::take_io_pending()
+    }
+
+    /// Enables the card interrupt again once the pending functions cleared
+    /// their interrupt sources
+    pub fn rearm_io_interrupt(&mut self) {
+        irq::rearm_io_interrupt();
+    }
+}
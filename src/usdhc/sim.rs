@@ -0,0 +1,108 @@
+//! # Simulated uSDHC
+//!
+//! `Registers` backend of the host tests. Every command written to
+//! CMD_XFR_TYP takes the next scripted `Reply`. Time advances by
+//! `CLOCK_STEP_US` whenever the clock is read, so inhibit and busy phases
+//! are given in microseconds.
+
+use std::collections::VecDeque;
+
+use super::regs::{int_status, pres_state, Register, Registers};
+
+/// Time that passes between two reads of the clock
+pub const CLOCK_STEP_US: u32 = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Reaction of the simulated card to the next command
+pub enum Reply {
+    /// CC with the response, DAT0 stays low for `busy_us` afterwards
+    Response { response: u32, busy_us: u32 },
+    /// the INT_STATUS error bits
+    Error(u32),
+    /// no reaction at all, INT_STATUS stays unchanged
+    Silent,
+}
+
+impl Reply {
+    pub fn response(response: u32) -> Self {
+        Reply::Response { response, busy_us: 0 }
+    }
+}
+
+#[derive(Default)]
+pub struct SimUsdhc {
+    pub int_status: u32,
+    pub auto_cmd12_err_status: u32,
+    /// time passed, see `CLOCK_STEP_US`
+    pub now_us: u32,
+    /// CIHB stays set until `now_us` reaches it
+    pub command_inhibit_until_us: u32,
+    /// CDIHB stays set until `now_us` reaches it
+    pub data_inhibit_until_us: u32,
+    /// DAT0 stays low until `now_us` reaches it
+    pub busy_until_us: u32,
+    /// (command index, argument) of every command sent
+    pub sent: Vec<(u32, u32)>,
+    pub replies: VecDeque<Reply>,
+    arg: u32,
+    response: u32,
+}
+
+impl SimUsdhc {
+    pub fn new(replies: &[Reply]) -> Self {
+        Self {
+            replies: replies.iter().copied().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Registers for SimUsdhc {
+    fn read(&self, register: Register) -> u32 {
+        match register {
+            Register::CmdArg => self.arg,
+            Register::CmdXfrTyp => 0,
+            Register::CmdRsp0 => self.response,
+            Register::IntStatus => self.int_status,
+            Register::AutoCmd12ErrStatus => self.auto_cmd12_err_status,
+            Register::PresState => {
+                let mut state = 0;
+                if self.now_us < self.command_inhibit_until_us {
+                    state |= pres_state::CIHB;
+                }
+                if self.now_us < self.data_inhibit_until_us {
+                    state |= pres_state::CDIHB;
+                }
+                if self.now_us >= self.busy_until_us {
+                    state |= pres_state::DAT0;
+                }
+                state
+            }
+        }
+    }
+
+    fn write(&mut self, register: Register, value: u32) {
+        match register {
+            Register::CmdArg => self.arg = value,
+            Register::CmdXfrTyp => {
+                self.sent.push(((value >> 24) & 0x3F, self.arg));
+                match self.replies.pop_front().unwrap_or(Reply::Silent) {
+                    Reply::Response { response, busy_us } => {
+                        self.response = response;
+                        self.busy_until_us = self.now_us.saturating_add(busy_us);
+                        self.int_status |= int_status::CC;
+                    }
+                    Reply::Error(bits) => self.int_status |= bits,
+                    Reply::Silent => {}
+                }
+            }
+            Register::IntStatus => self.int_status &= !value,
+            Register::CmdRsp0 | Register::PresState | Register::AutoCmd12ErrStatus => {}
+        }
+    }
+
+    fn now_us(&mut self) -> u32 {
+        self.now_us += CLOCK_STEP_US;
+        self.now_us
+    }
+}
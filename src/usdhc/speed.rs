@@ -0,0 +1,118 @@
+//! # Bus speed modes
+//!
+//! SD cards start in default speed (up to 25 MHz). Faster modes are
+//! negotiated with CMD6 SWITCH_FUNC, function group 1 (access mode):
+//!
+//! 1. CMD6 check mode, the status block lists the supported functions
+//! 2. CMD6 switch mode, the status block reports the selected function
+//! 3. SDCLK is raised, the pads are switched to faster edges
+//!
+//! The uSDHC has no high speed enable bit (HSEN of the SDHC standard), the
+//! timing only depends on SDCLK and the pad settings.
+
+use teensy4_bsp::{
+    hal::{self, iomuxc},
+    pins::imxrt_iomuxc::consts::Unsigned,
+    pins::imxrt_iomuxc::usdhc,
+};
+
+use super::{
+    commands,
+    constants,
+    registers::SwitchStatus,
+    transfer::{StopMode, Transfer},
+    CardMode, Error, USdhc,
+};
+
+/// Function group 1, access mode
+pub const ACCESS_MODE: u8 = 1;
+/// Access mode function of high speed / SDR25
+pub const ACCESS_MODE_HIGH_SPEED: u8 = 1;
+/// CCC class 10, switch
+const CCC_SWITCH: u16 = 1 << 10;
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// SDCLK in kHz
+    pub fn sd_clk_khz(&self) -> u32 {
+        self.sd_clk_khz
+    }
+
+    /// Sends CMD6 in check (`switch = false`) or switch mode for one
+    /// function group and returns the status block
+    pub fn switch_function(&mut self, switch: bool, group: u8, function: u8) -> Result<SwitchStatus, Error> {
+        if !(1..=6).contains(&group) || function > 0xF {
+            return Err(Error::InvalidArgument);
+        }
+        let mut bytes = [0u8; 64];
+        let transfer = Transfer::read(64, 1, StopMode::None);
+        self.read_data(commands::SwitchFunc::new(switch, group, function), &transfer, &mut bytes)?
+            .check()?;
+        Ok(SwitchStatus(bytes))
+    }
+
+    /// True if the card implements CMD6 (SD 1.10 and CCC class 10)
+    pub(crate) fn supports_switch(&self) -> bool {
+        let spec = self.scr.map(|scr| scr.sd_spec() >= 1).unwrap_or(false);
+        let class = self
+            .csd
+            .map(|csd| csd.command_classes() & CCC_SWITCH != 0)
+            .unwrap_or(false);
+        spec && class
+    }
+
+    /// Runs SDCLK at up to 25 MHz with the default pad settings
+    pub fn set_default_speed(&mut self, ccm: &mut hal::ccm::Handle) {
+        self.pins.set_pad_speed(iomuxc::Speed::Medium, iomuxc::DriveStrength::R0_6);
+        self.set_sd_clk(constants::SD_MAX_SD_DEFAULT_SPEED_RATE_HZ, false, ccm);
+        self.mode = CardMode::SdSdioFullSpeed;
+    }
+
+    /// Switches an SD card to high speed (50 MHz).
+    ///
+    /// Returns `Ok(false)` and runs at default speed if the card doesn't
+    /// support or refuses the switch, or if the bus fails the verification
+    /// (SCR read) at 50 MHz.
+    pub fn switch_to_high_speed(&mut self, ccm: &mut hal::ccm::Handle) -> Result<bool, Error> {
+        if !self.supports_switch() {
+            self.set_default_speed(ccm);
+            return Ok(false);
+        }
+
+        let status = self.switch_function(false, ACCESS_MODE, ACCESS_MODE_HIGH_SPEED)?;
+        if !status.supports(ACCESS_MODE, ACCESS_MODE_HIGH_SPEED) {
+            log::debug!("high speed not supported {:x}", status.supported(ACCESS_MODE));
+            self.set_default_speed(ccm);
+            return Ok(false);
+        }
+        let status = self.switch_function(true, ACCESS_MODE, ACCESS_MODE_HIGH_SPEED)?;
+        if status.selected(ACCESS_MODE) != ACCESS_MODE_HIGH_SPEED {
+            log::warn!("high speed switch refused {:x}", status.selected(ACCESS_MODE));
+            self.set_default_speed(ccm);
+            return Ok(false);
+        }
+
+        // the card switches within 8 clocks after the status block
+        self.pins.set_pad_speed(iomuxc::Speed::Fast, iomuxc::DriveStrength::R0_7);
+        self.set_sd_clk(constants::SD_MAX_SD_HIGH_SPEED_RATE_HZ, false, ccm);
+        self.mode = CardMode::SdSdioHighSpeed;
+
+        let expected = self.scr;
+        match self.read_scr() {
+            Ok(scr) if Some(scr) == expected => Ok(true),
+            result => {
+                log::warn!("high speed verification failed {:?}", result.err());
+                self.set_default_speed(ccm);
+                Ok(false)
+            }
+        }
+    }
+}
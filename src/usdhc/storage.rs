@@ -0,0 +1,178 @@
+//! # embedded-storage
+//!
+//! Implements the async `NorFlash` traits of `embedded-storage-async` on
+//! top of the block I/O, so file systems and key/value stores written
+//! against them run on an SD card.
+//!
+//! Offsets and lengths have to be multiples of `BLOCK_SIZE`. The offsets of
+//! the traits are `u32`, the capacity of cards over 4 GiB is reported as
+//! `MAX_CAPACITY` and the rest of the card is out of reach.
+//! Unlike NOR flash the card doesn't need an erase before a write, erased
+//! blocks read as all 0 or all 1 depending on the card (SCR
+//! DATA_STAT_AFTER_ERASE).
+//!
+//! Any block range can be erased. Cards with CSD ERASE_BLK_EN = 0 only erase
+//! whole sectors (`erase_unit`), the blocks of the partial sectors at both
+//! ends of the range are overwritten with the erased pattern instead.
+
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{block::BLOCK_SIZE, erase::EraseKind, error::WriteError, Error, USdhc};
+
+/// Largest capacity reachable with `u32` offsets, the last block below 4 GiB
+pub const MAX_CAPACITY: u64 = (1 << 32) - BLOCK_SIZE as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Errors of the `NorFlash` implementation
+pub enum StorageError {
+    /// Offset or length isn't a multiple of `BLOCK_SIZE`
+    NotAligned,
+    /// The range ends behind the capacity of the card
+    OutOfBounds,
+    /// The driver or the card failed
+    Card(Error),
+}
+
+impl From<Error> for StorageError {
+    fn from(err: Error) -> Self {
+        StorageError::Card(err)
+    }
+}
+
+impl From<WriteError> for StorageError {
+    fn from(err: WriteError) -> Self {
+        StorageError::Card(err.cause)
+    }
+}
+
+impl NorFlashError for StorageError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            StorageError::NotAligned => NorFlashErrorKind::NotAligned,
+            StorageError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            StorageError::Card(_) => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Checks `offset..offset + len` and returns the first block
+    fn storage_range(&self, offset: u32, len: usize) -> Result<u32, StorageError> {
+        if offset as usize % BLOCK_SIZE != 0 || len % BLOCK_SIZE != 0 {
+            return Err(StorageError::NotAligned);
+        }
+        let end = (offset as u64).checked_add(len as u64).ok_or(StorageError::OutOfBounds)?;
+        if end > self.capacity() as u64 {
+            return Err(StorageError::OutOfBounds);
+        }
+        Ok(offset / BLOCK_SIZE as u32)
+    }
+
+    /// Erases `start_lba..end_lba`, CMD38 for the whole erase units in the
+    /// range and the erased pattern written into the blocks around them
+    async fn erase_blocks(&mut self, start_lba: u32, end_lba: u32) -> Result<(), StorageError> {
+        let unit = self.erase_unit().ok_or(Error::Unsupported)?;
+        let units_start = start_lba.next_multiple_of(unit);
+        let units_end = end_lba / unit * unit;
+        if units_start >= units_end {
+            return self.fill_erased(start_lba, end_lba).await;
+        }
+        self.fill_erased(start_lba, units_start).await?;
+        self.erase_async(units_start, units_end - 1, EraseKind::Erase).await?;
+        self.fill_erased(units_end, end_lba).await
+    }
+
+    /// Writes the pattern erased blocks read as into `start_lba..end_lba`
+    async fn fill_erased(&mut self, start_lba: u32, end_lba: u32) -> Result<(), StorageError> {
+        let erased = if self.scr.is_some_and(|scr| scr.data_stat_after_erase()) {
+            0xFF
+        } else {
+            0
+        };
+        let block = [erased; BLOCK_SIZE];
+        for lba in start_lba..end_lba {
+            self.write_block_async(lba, &block).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> ErrorType for USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    type Error = StorageError;
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> ReadNorFlash for USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    const READ_SIZE: usize = BLOCK_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let lba = self.storage_range(offset, bytes.len())?;
+        self.read_vectored_async(lba, &mut [bytes]).await?;
+        Ok(())
+    }
+
+    /// Size of the user area from the CSD, 0 before the card is identified,
+    /// at most `MAX_CAPACITY`
+    fn capacity(&self) -> usize {
+        self.csd.map_or(0, |csd| {
+            let bytes = csd.block_count().saturating_mul(BLOCK_SIZE as u64);
+            bytes.min(MAX_CAPACITY) as usize
+        })
+    }
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> NorFlash for USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    const WRITE_SIZE: usize = BLOCK_SIZE;
+    /// Partial erase sectors are overwritten, see the module documentation
+    const ERASE_SIZE: usize = BLOCK_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(StorageError::Card(Error::InvalidArgument));
+        }
+        let start_lba = self.storage_range(from, (to - from) as usize)?;
+        self.erase_blocks(start_lba, to / BLOCK_SIZE as u32).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let lba = self.storage_range(offset, bytes.len())?;
+        self.write_vectored_async(lba, &[bytes]).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1028 @@
+//! # Data transfers
+//!
+//! Programs BLK_ATT, WTMK_LVL and MIX_CTRL for the data phase of a command
+//! and moves the data either through DATA_BUFF_ACC_PORT by polling BRR/BWR
+//! (CPU polling mode), with the internal simple/advanced DMA or with an
+//! external eDMA channel, see `buffer.rs`. With interrupts enabled the ISR
+//! moves the CPU polling data and the waits sleep, see `irq.rs`.
+//!
+//! The engine is written with `async fn`s, waiting for INT_STATUS bits is
+//! a future that is woken by the ISR. The blocking API runs the same code
+//! with `executor::block_on`. Commands are sent by `engine.rs` through the
+//! `Hardware` register backend.
+
+use core::task::{Context, Poll};
+use teensy4_bsp::{
+    hal::ral::{self, usdhc::INT_STATUS},
+    pins::imxrt_iomuxc::consts::Unsigned,
+    pins::imxrt_iomuxc::usdhc,
+};
+
+use super::{
+    adma::{Adma2Descriptor, AdmaErrorStatus, ADMA2_TABLE_LEN},
+    buffer::{TransferMode, Watermark, DMA_ALIGNMENT},
+    cache::{self, BOUNCE_BUFFER_LEN},
+    clock,
+    edma::ExternalDma,
+    commands::{self, DataDirection},
+    engine::{self, BUSY_TIMEOUT_US, DATA_INHIBIT_TIMEOUT_US},
+    error::{COMMAND_ERRORS, DATA_ERRORS},
+    executor::block_on,
+    irq,
+    registers::{CardStatus, CurrentState, IoStatus},
+    regs::{int_status, pres_state, Register, Registers},
+    Error, USdhc, ARM_CLOCK_HZ,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// How the end of a multi-block transfer is signaled to the card
+pub enum StopMode {
+    /// Single block transfer, nothing to stop
+    None,
+    /// The uSDHC sends CMD12 after the last block (MIX_CTRL[AC12EN])
+    AutoCmd12,
+    /// The block count is announced with CMD23 before the data command,
+    /// the card stops on its own
+    PreDefined,
+    /// SDIO CMD53, the byte/block count is part of the argument. There is
+    /// no card status to poll, the card is idle once DAT0 is released.
+    Io,
+    /// Single block the card answers outside the transfer state (eMMC bus
+    /// test), it isn't polled with CMD13 afterwards
+    NoStatus,
+    /// Single block followed by a busy phase longer than `BUSY_TIMEOUT_US`
+    /// (CMD42 forced erase), the caller waits for the end of the busy
+    /// signal with its own timeout and polls the status
+    LongBusy,
+}
+
+impl StopMode {
+    fn polls_status(&self) -> bool {
+        !matches!(self, StopMode::Io | StopMode::NoStatus)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// How the data of one transfer is moved
+pub enum DataPath {
+    /// BRR/BWR polling through DATA_BUFF_ACC_PORT
+    CpuPolling,
+    /// simple DMA from/to the system address
+    Sdma(u32),
+    /// advanced DMA with the descriptor table at the system address
+    Adma2(u32),
+    /// external eDMA channel from/to the system address, see `edma.rs`
+    ExternalDma(u32),
+}
+
+impl DataPath {
+    /// SDMA and ADMA2 are driven by the uSDHC itself (MIX_CTRL[DMAEN])
+    pub fn is_internal_dma(&self) -> bool {
+        matches!(self, DataPath::Sdma(_) | DataPath::Adma2(_))
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Description of the data phase of the next command
+pub struct Transfer {
+    pub direction: DataDirection,
+    pub block_size: u32,
+    pub block_count: u32,
+    pub stop: StopMode,
+}
+
+impl Transfer {
+    pub fn read(block_size: u32, block_count: u32, stop: StopMode) -> Self {
+        Self {
+            direction: DataDirection::Read,
+            block_size,
+            block_count,
+            stop,
+        }
+    }
+
+    pub fn write(block_size: u32, block_count: u32, stop: StopMode) -> Self {
+        Self {
+            direction: DataDirection::Write,
+            block_size,
+            block_count,
+            stop,
+        }
+    }
+
+    pub fn is_multi_block(&self) -> bool {
+        self.block_count > 1
+    }
+
+    /// Watermark in words for this transfer, see `Watermark::level_for`
+    pub fn watermark(&self, config: &Watermark) -> u32 {
+        config.level_for(self.direction, self.block_size)
+    }
+}
+
+/// Time RSTC/RSTD may take to clear themselves after a line reset
+const LINE_RESET_TIMEOUT_US: u32 = 1_000;
+
+/// `Registers` backend of the uSDHC, see `regs.rs`
+pub(crate) struct Hardware {
+    usdhc: *const ral::usdhc::RegisterBlock,
+    irq_enabled: bool,
+}
+
+impl Hardware {
+    /// USDHC1 without the driver instance, for the card interrupt (`irq.rs`).
+    /// `irq_enabled` has to be false inside the ISR, it can't wait for
+    /// itself.
+    pub(crate) fn usdhc1(irq_enabled: bool) -> Self {
+        Self {
+            usdhc: ral::usdhc::USDHC1,
+            irq_enabled,
+        }
+    }
+}
+
+impl Registers for Hardware {
+    fn read(&self, register: Register) -> u32 {
+        // Safety: `usdhc` points to the register block of the peripheral,
+        // which is valid for the whole program
+        unsafe {
+            match register {
+                Register::CmdArg => ral::read_reg!(ral::usdhc, self.usdhc, CMD_ARG),
+                Register::CmdXfrTyp => ral::read_reg!(ral::usdhc, self.usdhc, CMD_XFR_TYP),
+                Register::CmdRsp0 => ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP0),
+                Register::PresState => ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE),
+                Register::IntStatus => ral::read_reg!(ral::usdhc, self.usdhc, INT_STATUS),
+                Register::AutoCmd12ErrStatus => ral::read_reg!(ral::usdhc, self.usdhc, AUTOCMD12_ERR_STATUS),
+            }
+        }
+    }
+
+    /// Writing CMD_XFR_TYP also drops the CC/error bits the ISR recorded
+    /// for the previous command
+    fn write(&mut self, register: Register, value: u32) {
+        // Safety: see `read`
+        unsafe {
+            match register {
+                Register::CmdArg => ral::write_reg!(ral::usdhc, self.usdhc, CMD_ARG, value),
+                Register::CmdXfrTyp => {
+                    if self.irq_enabled {
+                        irq::clear(int_status::CC | COMMAND_ERRORS);
+                    }
+                    ral::write_reg!(ral::usdhc, self.usdhc, CMD_XFR_TYP, value)
+                }
+                Register::IntStatus => ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, value),
+                Register::CmdRsp0 | Register::PresState | Register::AutoCmd12ErrStatus => {}
+            }
+        }
+    }
+
+    fn now_us(&mut self) -> u32 {
+        clock::now_us()
+    }
+
+    /// Polls the register or, with interrupts enabled, waits until the ISR
+    /// recorded the bits and woke the task
+    fn poll_int_status(&mut self, mask: u32, cx: &mut Context<'_>) -> Poll<u32> {
+        if self.irq_enabled {
+            return irq::poll_status(mask, cx.waker());
+        }
+        let status = self.read(Register::IntStatus);
+        if status & mask != 0 {
+            Poll::Ready(status)
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// Sends a command and waits for the response.
+    ///
+    /// Returns CMD_RSP0, the card status for R1 responses. The data phase
+    /// has to be set up with `prepare_transfer` beforehand. An application
+    /// command is sent with its CMD55 prefix, no card interrupt is served
+    /// in between.
+    pub fn execute<C: commands::SdCommand>(&mut self, cmd: C) -> Result<u32, Error> {
+        block_on(self.execute_async(cmd))
+    }
+
+    /// Async version of `execute`
+    pub async fn execute_async<C: commands::SdCommand>(&mut self, cmd: C) -> Result<u32, Error> {
+        let _bus = irq::BusGuard::claim();
+        if cmd.req_app_cmd() {
+            let status = self.issue(commands::AppCmd::new(self.rca)).await?;
+            if !CardStatus(status).is_set(CardStatus::APP_CMD) && self.rca != 0 {
+                return Err(Error::Card(CardStatus(status)));
+            }
+        }
+        self.issue(cmd).await
+    }
+
+    /// Sends a single command, without the APP_CMD prefix. The caller holds
+    /// the bus.
+    async fn issue<C: commands::SdCommand>(&mut self, cmd: C) -> Result<u32, Error> {
+        log::debug!("execute cmd: {}", cmd.cmd_id());
+        engine::issue(&mut self.registers(), &cmd).await
+    }
+
+    /// The uSDHC as `Registers` backend of the command engine
+    pub(crate) fn registers(&self) -> Hardware {
+        Hardware {
+            usdhc: &*self.usdhc,
+            irq_enabled: self.irq_enabled,
+        }
+    }
+
+    /// Waits until one of the `done` bits is set in INT_STATUS.
+    ///
+    /// Polls the register or, with interrupts enabled, waits until the ISR
+    /// recorded the bits and woke the task. The error bits in `errors` are
+    /// cleared and mapped into an `Error`.
+    pub(crate) async fn wait_int_status(&mut self, done: u32, errors: u32) -> Result<u32, Error> {
+        engine::wait_int_status(&mut self.registers(), done, errors).await
+    }
+
+    /// wait until the data lines are no longer used by a previous transfer
+    pub fn wait_for_data_line(&mut self) -> Result<(), Error> {
+        let busy = |state: u32| state & pres_state::CDIHB != 0;
+        block_on(engine::wait_pres_state(&mut self.registers(), busy, DATA_INHIBIT_TIMEOUT_US))
+    }
+
+    /// wait until the card releases DAT0 (busy signaling of R1b commands
+    /// and of programming after a write)
+    pub fn wait_while_busy(&mut self) -> Result<(), Error> {
+        block_on(self.wait_while_busy_async())
+    }
+
+    /// `wait_while_busy` with a timeout, for the long busy phase of erase
+    /// commands. Fails with `Error::Timeout` if DAT0 is still low.
+    pub fn wait_while_busy_timeout(&mut self, timeout_ms: u32) -> Result<(), Error> {
+        block_on(self.wait_while_busy_timeout_async(timeout_ms))
+    }
+
+    /// Async version of `wait_while_busy`
+    pub async fn wait_while_busy_async(&mut self) -> Result<(), Error> {
+        let busy = |state: u32| state & pres_state::DAT0 == 0;
+        engine::wait_pres_state(&mut self.registers(), busy, BUSY_TIMEOUT_US).await
+    }
+
+    /// Async version of `wait_while_busy_timeout`
+    pub async fn wait_while_busy_timeout_async(&mut self, timeout_ms: u32) -> Result<(), Error> {
+        let busy = |state: u32| state & pres_state::DAT0 == 0;
+        engine::wait_pres_state(&mut self.registers(), busy, timeout_ms.saturating_mul(1000)).await
+    }
+
+    /// Selects how the data of the segments is moved.
+    ///
+    /// Falls back to CPU polling if the segments don't satisfy the alignment
+    /// of the internal DMA or SDMA would need more than one segment. Read
+    /// buffers in cached memory also have to be cache line aligned, see
+    /// `cache.rs`.
+    pub(crate) fn data_path(&mut self, transfer: &Transfer, segments: &[&[u8]]) -> DataPath {
+        let aligned = segments.iter().all(|s| {
+            let address = s.as_ptr() as usize;
+            address % DMA_ALIGNMENT == 0
+                && s.len() % DMA_ALIGNMENT == 0
+                && (transfer.direction != DataDirection::Read || cache::is_read_safe(address, s.len()))
+        });
+
+        match self.transfer_mode {
+            TransferMode::CpuPolling => DataPath::CpuPolling,
+            TransferMode::Sdma if aligned && segments.len() == 1 => {
+                DataPath::Sdma(segments[0].as_ptr() as u32)
+            }
+            TransferMode::ExternalDma
+                if aligned
+                    && segments.len() == 1
+                    && self.edma.is_some()
+                    && ExternalDma::iterations(segments[0].len(), transfer.watermark(&self.watermark) as usize).is_some() =>
+            {
+                DataPath::ExternalDma(segments[0].as_ptr() as u32)
+            }
+            TransferMode::Adma2 if aligned => {
+                match self
+                    .adma_table
+                    .build(segments.iter().map(|s| (s.as_ptr() as u32, s.len())))
+                {
+                    Ok(_) => DataPath::Adma2(self.adma_table.address()),
+                    Err(err) => {
+                        log::debug!("adma table {:?}, fall back to cpu polling", err);
+                        DataPath::CpuPolling
+                    }
+                }
+            }
+            _ => {
+                log::debug!("unaligned buffer, fall back to cpu polling");
+                DataPath::CpuPolling
+            }
+        }
+    }
+
+    /// Configures block size/count, the watermark and the MIX_CTRL transfer
+    /// bits for the data phase of the next command.
+    ///
+    /// For the internal DMA paths the engine is selected in PROT_CTRL[DMASEL]
+    /// and started from DS_ADDR (SDMA) or ADMA_SYS_ADDR (ADMA2).
+    pub(crate) fn prepare_transfer(&mut self, transfer: &Transfer, path: DataPath) -> Result<(), Error> {
+        if transfer.block_size == 0
+            || transfer.block_size > 4096
+            || transfer.block_count == 0
+            || transfer.block_count > 65535
+        {
+            return Err(Error::InvalidArgument);
+        }
+        self.wait_for_data_line()?;
+
+        let wml = transfer.watermark(&self.watermark);
+        match transfer.direction {
+            DataDirection::Write => {
+                ral::modify_reg!(ral::usdhc, self.usdhc, WTMK_LVL, WR_WML: wml)
+            }
+            _ => ral::modify_reg!(ral::usdhc, self.usdhc, WTMK_LVL, RD_WML: wml),
+        }
+
+        ral::write_reg!(
+            ral::usdhc,
+            self.usdhc,
+            BLK_ATT,
+            BLKCNT: transfer.block_count,
+            BLKSIZE: transfer.block_size
+        );
+
+        match path {
+            DataPath::CpuPolling => {}
+            DataPath::ExternalDma(address) => {
+                let port = &self.usdhc.DATA_BUFF_ACC_PORT as *const _ as *const u32;
+                let len = (transfer.block_size * transfer.block_count) as usize;
+                let edma = self.edma.as_mut().ok_or(Error::Unsupported)?;
+                // Safety: the buffer is borrowed by read_data/write_data until
+                // the transfer finished or was aborted
+                unsafe {
+                    match transfer.direction {
+                        DataDirection::Write => edma.prepare_write(port, address as *const u32, len, wml as usize),
+                        _ => edma.prepare_read(port, address as *mut u32, len, wml as usize),
+                    }
+                }
+                edma.enable();
+            }
+            DataPath::Sdma(address) => {
+                ral::modify_reg!(ral::usdhc, self.usdhc, PROT_CTRL, DMASEL: 0b00);
+                ral::write_reg!(ral::usdhc, self.usdhc, DS_ADDR, address);
+            }
+            DataPath::Adma2(table) => {
+                // the ADMA engine fetches the descriptors from memory as well
+                let len = self.adma_table.descriptors().len() * core::mem::size_of::<Adma2Descriptor>();
+                cache::clean(table as usize, len);
+                ral::modify_reg!(ral::usdhc, self.usdhc, PROT_CTRL, DMASEL: 0b10);
+                ral::write_reg!(ral::usdhc, self.usdhc, ADMA_SYS_ADDR, table);
+            }
+        }
+
+        let multi = transfer.is_multi_block() as u32;
+        ral::modify_reg!(
+            ral::usdhc,
+            self.usdhc,
+            MIX_CTRL,
+            DMAEN: path.is_internal_dma() as u32,
+            BCEN: multi,
+            MSBSEL: multi,
+            AC12EN: (multi == 1 && transfer.stop == StopMode::AutoCmd12) as u32,
+            AC23EN: 0,
+            DTDSEL: (transfer.direction == DataDirection::Read) as u32
+        );
+
+        if self.irq_enabled {
+            // only the transfers driven by BRR/BWR signal them, with DMA
+            // they would fire for every watermark
+            let buffer_ready = match path {
+                DataPath::CpuPolling => true,
+                DataPath::ExternalDma(_) => !self
+                    .edma
+                    .as_ref()
+                    .map(|edma| edma.is_hardware_triggered())
+                    .unwrap_or(true),
+                _ => false,
+            };
+            let read = transfer.direction == DataDirection::Read;
+            ral::modify_reg!(
+                ral::usdhc,
+                self.usdhc,
+                INT_SIGNAL_EN,
+                BRRIEN: (buffer_ready && read) as u32,
+                BWRIEN: (buffer_ready && !read) as u32
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Masks BRR/BWR for the ISR again once the transfer is over
+    fn mask_buffer_ready(&mut self) {
+        if self.irq_enabled {
+            ral::modify_reg!(ral::usdhc, self.usdhc, INT_SIGNAL_EN, BRRIEN: 0, BWRIEN: 0);
+        }
+    }
+
+    /// Reads `buffer.len()` bytes from the data port, one watermark at a
+    /// time whenever BRR is set.
+    pub(crate) async fn read_pio(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let wml = ral::read_reg!(ral::usdhc, self.usdhc, WTMK_LVL, RD_WML).max(1) as usize;
+        let mut offset = 0;
+        while offset < buffer.len() {
+            self.wait_int_status(INT_STATUS::BRR::mask, DATA_ERRORS).await?;
+            ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, INT_STATUS::BRR::mask);
+
+            for _ in 0..wml {
+                if offset >= buffer.len() {
+                    break;
+                }
+                let word = ral::read_reg!(ral::usdhc, self.usdhc, DATA_BUFF_ACC_PORT).to_le_bytes();
+                let len = (buffer.len() - offset).min(4);
+                buffer[offset..offset + len].copy_from_slice(&word[..len]);
+                offset += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `buffer` into the data port, one watermark at a time whenever
+    /// BWR is set.
+    pub(crate) async fn write_pio(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        let wml = ral::read_reg!(ral::usdhc, self.usdhc, WTMK_LVL, WR_WML).max(1) as usize;
+        let mut offset = 0;
+        while offset < buffer.len() {
+            self.wait_int_status(INT_STATUS::BWR::mask, DATA_ERRORS).await?;
+            ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, INT_STATUS::BWR::mask);
+
+            for _ in 0..wml {
+                if offset >= buffer.len() {
+                    break;
+                }
+                let len = (buffer.len() - offset).min(4);
+                let mut word = [0u8; 4];
+                word[..len].copy_from_slice(&buffer[offset..offset + len]);
+                ral::write_reg!(ral::usdhc, self.usdhc, DATA_BUFF_ACC_PORT, u32::from_le_bytes(word));
+                offset += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the card finished programming: DAT line no longer active
+    /// (PRES_STATE[DLA]) and DAT0 released (PRES_STATE[DLSL])
+    pub fn wait_for_programming(&mut self) -> Result<(), Error> {
+        block_on(self.wait_for_programming_async())
+    }
+
+    /// Async version of `wait_for_programming`
+    pub async fn wait_for_programming_async(&mut self) -> Result<(), Error> {
+        let active = |state: u32| state & pres_state::DLA != 0;
+        engine::wait_pres_state(&mut self.registers(), active, BUSY_TIMEOUT_US).await?;
+        self.wait_while_busy_async().await
+    }
+
+    /// Polls CMD13 until the card is back in the transfer state and ready
+    /// for data, returns the last card status. Gives up with
+    /// `Error::Timeout` after `engine::TRANSFER_STATE_POLLS` commands.
+    pub fn wait_for_transfer_state(&mut self) -> Result<CardStatus, Error> {
+        block_on(self.wait_for_transfer_state_async())
+    }
+
+    /// Async version of `wait_for_transfer_state`
+    pub async fn wait_for_transfer_state_async(&mut self) -> Result<CardStatus, Error> {
+        let _bus = irq::BusGuard::claim();
+        let rca = self.rca;
+        engine::wait_for_transfer_state(&mut self.registers(), rca).await
+    }
+
+    /// Waits for transfer complete (TC) of the current data transfer
+    pub(crate) async fn finish_transfer(&mut self) -> Result<(), Error> {
+        self.wait_int_status(INT_STATUS::TC::mask, DATA_ERRORS).await?;
+        ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, INT_STATUS::TC::mask);
+        Ok(())
+    }
+
+    /// Waits for the end of an internal DMA transfer.
+    ///
+    /// The SDMA pauses at every buffer boundary with DINT set, DS_ADDR then
+    /// holds the next system address and writing it back resumes the transfer.
+    /// An ADMA error is reported with the decoded ADMA_ERR_STATUS.
+    ///
+    /// The boundaries are at least 4 KiB apart, a transfer that raises more
+    /// than one DINT per block is given up with `Error::Timeout`.
+    pub(crate) async fn finish_dma(&mut self, transfer: &Transfer, path: DataPath) -> Result<(), Error> {
+        for _ in 0..=transfer.block_count {
+            let status = match self
+                .wait_int_status(INT_STATUS::TC::mask | INT_STATUS::DINT::mask, DATA_ERRORS)
+                .await
+            {
+                Ok(status) => status,
+                Err(Error::Dma) if matches!(path, DataPath::Adma2(_)) => {
+                    let adma = ral::read_reg!(ral::usdhc, self.usdhc, ADMA_ERR_STATUS);
+                    return Err(Error::Adma(AdmaErrorStatus(adma)));
+                }
+                Err(err) => return Err(err),
+            };
+            if status & INT_STATUS::TC::mask != 0 {
+                ral::write_reg!(
+                    ral::usdhc,
+                    self.usdhc,
+                    INT_STATUS,
+                    INT_STATUS::TC::mask | INT_STATUS::DINT::mask
+                );
+                return Ok(());
+            }
+            ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, INT_STATUS::DINT::mask);
+            if let DataPath::Sdma(_) = path {
+                let next = ral::read_reg!(ral::usdhc, self.usdhc, DS_ADDR);
+                ral::write_reg!(ral::usdhc, self.usdhc, DS_ADDR, next);
+            }
+        }
+        log::error!("dma transfer did not complete");
+        Err(Error::Timeout)
+    }
+
+    /// Waits for the end of an external eDMA transfer.
+    ///
+    /// Software triggered channels are started for every BRR/BWR, hardware
+    /// triggered channels run on their own while the uSDHC errors are watched.
+    /// Every trigger moves at least one word, a channel that needs more
+    /// triggers than the transfer has words is given up with `Error::Timeout`.
+    pub(crate) async fn finish_external_dma(&mut self, transfer: &Transfer) -> Result<(), Error> {
+        let mut edma = self.edma.take().ok_or(Error::Unsupported)?;
+        let ready = match transfer.direction {
+            DataDirection::Write => INT_STATUS::BWR::mask,
+            _ => INT_STATUS::BRR::mask,
+        };
+
+        let result = if edma.is_hardware_triggered() {
+            // TC is only set after the last word went through the data port
+            match self.finish_transfer().await {
+                Ok(()) => match edma.poll() {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(Error::Dma),
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err),
+            }
+        } else {
+            let words = transfer.block_count * (transfer.block_size / 4).max(1);
+            let mut moved = Err(Error::Timeout);
+            for _ in 0..=words {
+                match edma.poll() {
+                    Ok(true) => {
+                        moved = Ok(());
+                        break;
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        moved = Err(err);
+                        break;
+                    }
+                }
+                if let Err(err) = self.wait_int_status(ready, DATA_ERRORS).await {
+                    moved = Err(err);
+                    break;
+                }
+                ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, ready);
+                edma.trigger();
+                if let Err(err) = edma.wait_minor_loop().await {
+                    moved = Err(err);
+                    break;
+                }
+            }
+            match moved {
+                Ok(()) => self.finish_transfer().await,
+                Err(err) => Err(err),
+            }
+        };
+
+        edma.stop();
+        self.edma = Some(edma);
+        result
+    }
+
+    /// CPU polling read spread over several segments
+    pub(crate) async fn read_pio_vectored(&mut self, segments: &mut [&mut [u8]]) -> Result<(), Error> {
+        let wml = ral::read_reg!(ral::usdhc, self.usdhc, WTMK_LVL, RD_WML).max(1) as usize;
+        let total: usize = segments.iter().map(|s| s.len()).sum();
+        let (mut segment, mut offset, mut done) = (0, 0, 0);
+        while done < total {
+            self.wait_int_status(INT_STATUS::BRR::mask, DATA_ERRORS).await?;
+            ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, INT_STATUS::BRR::mask);
+
+            for _ in 0..wml {
+                if done >= total {
+                    break;
+                }
+                let word = ral::read_reg!(ral::usdhc, self.usdhc, DATA_BUFF_ACC_PORT).to_le_bytes();
+                for byte in word.iter().take(total - done) {
+                    while offset >= segments[segment].len() {
+                        segment += 1;
+                        offset = 0;
+                    }
+                    segments[segment][offset] = *byte;
+                    offset += 1;
+                    done += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// CPU polling write gathered from several segments
+    pub(crate) async fn write_pio_vectored(&mut self, segments: &[&[u8]]) -> Result<(), Error> {
+        let wml = ral::read_reg!(ral::usdhc, self.usdhc, WTMK_LVL, WR_WML).max(1) as usize;
+        let mut bytes = segments.iter().flat_map(|s| s.iter().copied()).peekable();
+        while bytes.peek().is_some() {
+            self.wait_int_status(INT_STATUS::BWR::mask, DATA_ERRORS).await?;
+            ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, INT_STATUS::BWR::mask);
+
+            for _ in 0..wml {
+                if bytes.peek().is_none() {
+                    break;
+                }
+                let mut word = [0u8; 4];
+                for (dst, src) in word.iter_mut().zip(&mut bytes) {
+                    *dst = src;
+                }
+                ral::write_reg!(ral::usdhc, self.usdhc, DATA_BUFF_ACC_PORT, u32::from_le_bytes(word));
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes an adtc read command and reads `buffer`.
+    ///
+    /// `buffer.len()` has to be `transfer.block_size * transfer.block_count`.
+    pub(crate) fn read_data<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        buffer: &mut [u8],
+    ) -> Result<CardStatus, Error> {
+        block_on(self.read_data_vectored_async(cmd, transfer, &mut [buffer]))
+    }
+
+    /// Async version of `read_data`
+    pub(crate) async fn read_data_async<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        buffer: &mut [u8],
+    ) -> Result<CardStatus, Error> {
+        self.read_data_vectored_async(cmd, transfer, &mut [buffer]).await
+    }
+
+    /// Executes an adtc read command and scatters the data over `segments`.
+    ///
+    /// The total length of the segments has to be
+    /// `transfer.block_size * transfer.block_count`.
+    pub(crate) fn read_data_vectored<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        segments: &mut [&mut [u8]],
+    ) -> Result<CardStatus, Error> {
+        block_on(self.read_data_vectored_async(cmd, transfer, segments))
+    }
+
+    /// Async version of `read_data_vectored`
+    pub(crate) async fn read_data_vectored_async<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        segments: &mut [&mut [u8]],
+    ) -> Result<CardStatus, Error> {
+        let total: usize = segments.iter().map(|s| s.len()).sum();
+        if total != (transfer.block_size * transfer.block_count) as usize {
+            return Err(Error::InvalidArgument);
+        }
+        let path = {
+            let mut views: [&[u8]; ADMA2_TABLE_LEN] = [&[]; ADMA2_TABLE_LEN];
+            if segments.len() > views.len() {
+                DataPath::CpuPolling
+            } else {
+                for (view, segment) in views.iter_mut().zip(segments.iter()) {
+                    *view = segment;
+                }
+                self.data_path(transfer, &views[..segments.len()])
+            }
+        };
+
+        if self.use_bounce_buffer(path, total) {
+            let bounce = self.bounce.0.as_mut_ptr();
+            // Safety: the bounce buffer is only used by this transfer while
+            // `self` is borrowed
+            let path = self.data_path(transfer, &[unsafe { core::slice::from_raw_parts(bounce, total) }]);
+            let status = self
+                .read_segments(
+                    cmd,
+                    transfer,
+                    &mut [unsafe { core::slice::from_raw_parts_mut(bounce, total) }],
+                    path,
+                )
+                .await?;
+            let mut offset = 0;
+            for segment in segments.iter_mut() {
+                segment.copy_from_slice(&self.bounce.0[offset..offset + segment.len()]);
+                offset += segment.len();
+            }
+            return Ok(status);
+        }
+        self.read_segments(cmd, transfer, segments, path).await
+    }
+
+    async fn read_segments<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        segments: &mut [&mut [u8]],
+        path: DataPath,
+    ) -> Result<CardStatus, Error> {
+        let _bus = irq::BusGuard::claim();
+        if path != DataPath::CpuPolling {
+            for segment in segments.iter() {
+                // Safety: `data_path` only selects a DMA path for line aligned
+                // read buffers
+                unsafe { cache::invalidate(segment.as_ptr() as usize, segment.len()) };
+            }
+        }
+        self.prepare_transfer(transfer, path)?;
+
+        // with interrupts the ISR serves BRR, it needs the buffer before the
+        // first watermark arrives
+        let irq_pio = self.irq_enabled && path == DataPath::CpuPolling;
+        if irq_pio {
+            // Safety: detached below before `segments` is released
+            unsafe { irq::attach_read(segments) };
+        }
+
+        let status = match self.execute_async(cmd).await {
+            Ok(status) => status,
+            Err(err) => {
+                if irq_pio {
+                    irq::detach();
+                }
+                self.mask_buffer_ready();
+                self.reset_data_line().ok();
+                return Err(err);
+            }
+        };
+
+        let result = match Self::check_response::<C>(status) {
+            Err(err) => Err(err),
+            Ok(_) => match path {
+                DataPath::CpuPolling if irq_pio => self.finish_transfer().await,
+                DataPath::CpuPolling => {
+                    let moved = if segments.len() == 1 {
+                        self.read_pio(&mut segments[0][..]).await
+                    } else {
+                        self.read_pio_vectored(segments).await
+                    };
+                    match moved {
+                        Ok(()) => self.finish_transfer().await,
+                        Err(err) => Err(err),
+                    }
+                }
+                DataPath::ExternalDma(_) => self.finish_external_dma(transfer).await,
+                _ => self.finish_dma(transfer, path).await,
+            },
+        };
+        if irq_pio {
+            irq::detach();
+        }
+        self.mask_buffer_ready();
+
+        if let Err(err) = result {
+            self.abort_transfer(transfer).await;
+            return Err(err);
+        }
+        if path != DataPath::CpuPolling {
+            // drop lines the core speculatively fetched during the transfer
+            for segment in segments.iter() {
+                unsafe { cache::invalidate(segment.as_ptr() as usize, segment.len()) };
+            }
+        }
+        Ok(CardStatus(status))
+    }
+
+    /// Executes an adtc write command and writes `buffer`.
+    ///
+    /// Returns after the card finished programming, with the CMD13 status
+    /// checked for errors.
+    pub(crate) fn write_data<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        buffer: &[u8],
+    ) -> Result<CardStatus, Error> {
+        block_on(self.write_data_vectored_async(cmd, transfer, &[buffer]))
+    }
+
+    /// Async version of `write_data`
+    pub(crate) async fn write_data_async<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        buffer: &[u8],
+    ) -> Result<CardStatus, Error> {
+        self.write_data_vectored_async(cmd, transfer, &[buffer]).await
+    }
+
+    /// Executes an adtc write command and gathers the data from `segments`.
+    pub(crate) fn write_data_vectored<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        segments: &[&[u8]],
+    ) -> Result<CardStatus, Error> {
+        block_on(self.write_data_vectored_async(cmd, transfer, segments))
+    }
+
+    /// Async version of `write_data_vectored`
+    pub(crate) async fn write_data_vectored_async<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        segments: &[&[u8]],
+    ) -> Result<CardStatus, Error> {
+        let total: usize = segments.iter().map(|s| s.len()).sum();
+        if total != (transfer.block_size * transfer.block_count) as usize {
+            return Err(Error::InvalidArgument);
+        }
+        let path = self.data_path(transfer, segments);
+
+        if self.use_bounce_buffer(path, total) {
+            let mut offset = 0;
+            for segment in segments {
+                self.bounce.0[offset..offset + segment.len()].copy_from_slice(segment);
+                offset += segment.len();
+            }
+            // Safety: the bounce buffer is only used by this transfer while
+            // `self` is borrowed
+            let bounce = unsafe { core::slice::from_raw_parts(self.bounce.0.as_ptr(), total) };
+            let path = self.data_path(transfer, &[bounce]);
+            return self.write_segments(cmd, transfer, &[bounce], path).await;
+        }
+        self.write_segments(cmd, transfer, segments, path).await
+    }
+
+    async fn write_segments<C: commands::SdCommand>(
+        &mut self,
+        cmd: C,
+        transfer: &Transfer,
+        segments: &[&[u8]],
+        path: DataPath,
+    ) -> Result<CardStatus, Error> {
+        let _bus = irq::BusGuard::claim();
+        if path != DataPath::CpuPolling {
+            for segment in segments {
+                cache::clean(segment.as_ptr() as usize, segment.len());
+            }
+        }
+        self.prepare_transfer(transfer, path)?;
+
+        let irq_pio = self.irq_enabled && path == DataPath::CpuPolling;
+        if irq_pio {
+            // Safety: detached below before `segments` is released
+            unsafe { irq::attach_write(segments) };
+        }
+
+        let status = match self.execute_async(cmd).await {
+            Ok(status) => status,
+            Err(err) => {
+                if irq_pio {
+                    irq::detach();
+                }
+                self.mask_buffer_ready();
+                self.reset_data_line().ok();
+                return Err(err);
+            }
+        };
+
+        let result = match Self::check_response::<C>(status) {
+            Err(err) => Err(err),
+            Ok(_) => match path {
+                DataPath::CpuPolling if irq_pio => self.finish_transfer().await,
+                DataPath::CpuPolling => {
+                    let moved = if segments.len() == 1 {
+                        self.write_pio(segments[0]).await
+                    } else {
+                        self.write_pio_vectored(segments).await
+                    };
+                    match moved {
+                        Ok(()) => self.finish_transfer().await,
+                        Err(err) => Err(err),
+                    }
+                }
+                DataPath::ExternalDma(_) => self.finish_external_dma(transfer).await,
+                _ => self.finish_dma(transfer, path).await,
+            },
+        };
+        if irq_pio {
+            irq::detach();
+        }
+        self.mask_buffer_ready();
+
+        if let Err(err) = result {
+            self.abort_transfer(transfer).await;
+            return Err(err);
+        }
+
+        if transfer.stop == StopMode::LongBusy {
+            return Ok(CardStatus(status));
+        }
+        self.wait_for_programming_async().await?;
+        if !transfer.stop.polls_status() {
+            return Ok(CardStatus(status));
+        }
+        self.wait_for_transfer_state_async().await
+    }
+
+    /// Checks the R1 card status, or the R5 flags of SDIO commands
+    fn check_response<C: commands::SdCommand>(status: u32) -> Result<(), Error> {
+        match C::RESPONSE {
+            commands::Response::R5 | commands::Response::R5b => IoStatus(status).check().map(|_| ()),
+            _ => CardStatus(status).check().map(|_| ()),
+        }
+    }
+
+    /// Unaligned buffers that would fall back to CPU polling go through the
+    /// bounce buffer instead if a DMA mode is selected and the data fits
+    fn use_bounce_buffer(&self, path: DataPath, total: usize) -> bool {
+        path == DataPath::CpuPolling
+            && total <= BOUNCE_BUFFER_LEN
+            && match self.transfer_mode {
+                TransferMode::CpuPolling => false,
+                TransferMode::ExternalDma => self.edma.is_some(),
+                _ => true,
+            }
+    }
+
+    /// Software reset for the CMD and DATA line (SYS_CTRL[RSTC], SYS_CTRL[RSTD])
+    ///
+    /// Fails with `Error::Timeout` if the reset bits don't clear themselves
+    /// within `LINE_RESET_TIMEOUT_US`, the failure is logged. On the
+    /// recovery paths the original error is reported instead.
+    pub(crate) fn reset_data_line(&mut self) -> Result<(), Error> {
+        ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTC: 1, RSTD: 1);
+        let mut waited_us = 0;
+        let resetting = ral::usdhc::SYS_CTRL::RSTC::mask | ral::usdhc::SYS_CTRL::RSTD::mask;
+        while ral::read_reg!(ral::usdhc, self.usdhc, SYS_CTRL) & resetting != 0 {
+            if waited_us >= LINE_RESET_TIMEOUT_US {
+                log::error!("CMD/DATA line reset didn't complete");
+                return Err(Error::Timeout);
+            }
+            cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000_000);
+            waited_us += 1;
+        }
+        ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, COMMAND_ERRORS | DATA_ERRORS);
+        Ok(())
+    }
+
+    /// Brings the card and the uSDHC back into a usable state after a
+    /// failed data transfer.
+    ///
+    /// The uSDHC doesn't send the auto CMD12 if the transfer was aborted,
+    /// so a multi-block transfer is stopped manually. Afterwards the card is
+    /// polled with CMD13 until it is back in the transfer state. SDIO
+    /// transfers and the bus test only reset the data line.
+    pub(crate) async fn abort_transfer(&mut self, transfer: &Transfer) {
+        if !transfer.stop.polls_status() {
+            self.reset_data_line().ok();
+            return;
+        }
+        if transfer.is_multi_block() {
+            if let Err(err) = self.execute_async(commands::StopTransmission::new()).await {
+                log::warn!("stop transmission failed {:?}", err);
+            }
+        }
+        if self.reset_data_line().is_err() {
+            return;
+        }
+
+        for _ in 0..1000 {
+            match self.execute_async(commands::SendStatus::new(self.rca)).await {
+                Ok(status) if CardStatus(status).current_state() == CurrentState::Tran => return,
+                Ok(_) => {}
+                Err(_) => {
+                    if self.reset_data_line().is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        log::error!("card did not return to transfer state");
+    }
+}
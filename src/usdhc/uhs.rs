@@ -0,0 +1,203 @@
+//! # UHS-I
+//!
+//! UHS-I modes need 1.8V signaling and a 4 bit bus. The switch to 1.8V is
+//! requested with S18R in ACMD41 (`Ocr::S18A` in the response) and done
+//! right after it, before CMD2:
+//!
+//! 1. CMD11, the card drives CMD and DAT[3:0] low
+//! 2. SDCLK is stopped (FRC_SDCLK_ON cleared, wait for PRES_STATE[SDOFF])
+//! 3. DAT[3:0] have to be low (PRES_STATE[DLSL])
+//! 4. VEND_SPEC[VSELECT] switches the pads to 1.8V, wait 5 ms
+//! 5. SDCLK is restarted, the card releases DAT[3:0] within 1 ms
+//! 6. DAT[3:0] have to be high, otherwise the card needs a power cycle
+//!
+//! Once CMD11 is accepted the card can only be brought back by a power
+//! cycle, failures after it are reported as `Error::VoltageSwitch`.
+//!
+//! Once the card is in the transfer state with a 4 bit bus, the bus speed
+//! mode is selected with CMD6 function group 1.
+
+use teensy4_bsp::{
+    hal::{self, iomuxc, ral},
+    pins::imxrt_iomuxc::consts::Unsigned,
+    pins::imxrt_iomuxc::usdhc,
+};
+
+use super::{
+    commands,
+    mode_switch::TuningCommand,
+    registers::{CardStatus, Ocr},
+    speed::ACCESS_MODE,
+    BusWidth, CardMode, Error, USdhc, ARM_CLOCK_HZ,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Bus speed modes of function group 1, the value is the function number
+pub enum UhsMode {
+    /// 25 MHz SDR
+    Sdr12 = 0,
+    /// 50 MHz SDR
+    Sdr25 = 1,
+    /// 100 MHz SDR, needs tuning
+    Sdr50 = 2,
+    /// 208 MHz SDR, needs tuning
+    Sdr104 = 3,
+    /// 50 MHz DDR
+    Ddr50 = 4,
+}
+
+impl UhsMode {
+    /// Fastest first
+    const PREFERENCE: [UhsMode; 5] = [
+        UhsMode::Sdr104,
+        UhsMode::Sdr50,
+        UhsMode::Ddr50,
+        UhsMode::Sdr25,
+        UhsMode::Sdr12,
+    ];
+
+    /// Maximum SDCLK
+    pub fn max_clock_hz(&self) -> u32 {
+        match self {
+            UhsMode::Sdr12 => 25_000_000,
+            UhsMode::Sdr25 | UhsMode::Ddr50 => 50_000_000,
+            UhsMode::Sdr50 => 100_000_000,
+            UhsMode::Sdr104 => 208_000_000,
+        }
+    }
+
+    /// SDR50 and SDR104 need the sampling point tuned with CMD19
+    pub fn needs_tuning(&self) -> bool {
+        matches!(self, UhsMode::Sdr50 | UhsMode::Sdr104)
+    }
+}
+
+/// Time SDCLK may take to stop after FRC_SDCLK_ON is cleared
+const SDCLK_OFF_TIMEOUT_US: u32 = 1_000;
+
+fn delay_ms(ms: u32) {
+    cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000 * ms);
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// True if the pads run at 1.8V (VEND_SPEC[VSELECT])
+    pub fn is_1v8_signaling(&self) -> bool {
+        ral::read_reg!(ral::usdhc, self.usdhc, VEND_SPEC, VSELECT == 1)
+    }
+
+    /// True if the uSDHC (HOST_CTRL_CAP[VS18]) and the board
+    /// (`Builder::signaling_1v8`) support 1.8V signaling, set S18R in
+    /// ACMD41 only then
+    pub fn host_supports_1v8(&self) -> bool {
+        self.wired_1v8 && ral::read_reg!(ral::usdhc, self.usdhc, HOST_CTRL_CAP, VS18 == 1)
+    }
+
+    /// Switches the signaling to 1.8V after ACMD41, `ocr` is its response.
+    ///
+    /// Fails with `Error::Unsupported` if the host or the card doesn't
+    /// support 1.8V, before anything is sent. Once the card accepted CMD11,
+    /// a stuck SDCLK fails with `Error::Timeout` and DAT[3:0] at the wrong
+    /// level with `Error::VoltageSwitch`, the pads are set back to 3.3V and
+    /// the card has to be power cycled.
+    pub fn switch_to_1v8(&mut self, ocr: u32) -> Result<(), Error> {
+        if !self.host_supports_1v8() || !Ocr(ocr).accepts_1v8() {
+            return Err(Error::Unsupported);
+        }
+        if self.is_1v8_signaling() {
+            return Ok(());
+        }
+
+        let status = self.execute(commands::VoltageSwitch::new())?;
+        CardStatus(status).check()?;
+
+        ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 0);
+        let mut waited_us = 0;
+        while ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE, SDOFF == 0) {
+            if waited_us >= SDCLK_OFF_TIMEOUT_US {
+                log::error!("SDCLK didn't stop for the 1.8V switch");
+                ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 1);
+                return Err(Error::Timeout);
+            }
+            cortex_m::asm::delay(ARM_CLOCK_HZ / 1_000_000);
+            waited_us += 1;
+        }
+
+        if ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE, DLSL) & 0b1111 != 0 {
+            log::error!("card didn't drive DAT[3:0] low after CMD11, power cycle the card");
+            ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 1);
+            return Err(Error::VoltageSwitch);
+        }
+
+        ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, VSELECT: 1);
+        delay_ms(5);
+
+        ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, FRC_SDCLK_ON: 1);
+        delay_ms(1);
+
+        if ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE, DLSL) & 0b1111 != 0b1111 {
+            log::error!("1.8V switch failed, power cycle the card");
+            ral::modify_reg!(ral::usdhc, self.usdhc, VEND_SPEC, VSELECT: 0);
+            return Err(Error::VoltageSwitch);
+        }
+        log::debug!("1.8V signaling");
+        Ok(())
+    }
+
+    /// Selects the fastest UHS-I mode supported by card and host with CMD6
+    /// and raises SDCLK, DDR50 enables MIX_CTRL[DDR_EN].
+    ///
+    /// Needs 1.8V signaling and a 4 bit bus (`set_bus_width`).
+    pub fn switch_to_uhs(&mut self, ccm: &mut hal::ccm::Handle) -> Result<UhsMode, Error> {
+        if !self.is_1v8_signaling() || self.bus_width() != BusWidth::Four || !self.supports_switch() {
+            return Err(Error::Unsupported);
+        }
+        let status = self.switch_function(false, ACCESS_MODE, 0xF)?;
+        let mode = UhsMode::PREFERENCE
+            .iter()
+            .copied()
+            .find(|mode| status.supports(ACCESS_MODE, *mode as u8))
+            .ok_or(Error::Unsupported)?;
+        self.select_uhs_mode(mode, ccm)?;
+        Ok(mode)
+    }
+
+    /// Switches the card to `mode` and configures SDCLK, pads and DDR.
+    ///
+    /// SDR50 and SDR104 are tuned right away (manual sweep if the standard
+    /// tuning fails), a failed tuning falls back to the fixed sampling clock
+    /// and is reported as error.
+    pub fn select_uhs_mode(&mut self, mode: UhsMode, ccm: &mut hal::ccm::Handle) -> Result<(), Error> {
+        if !self.is_1v8_signaling() || self.bus_width() != BusWidth::Four {
+            return Err(Error::Unsupported);
+        }
+        let status = self.switch_function(true, ACCESS_MODE, mode as u8)?;
+        if status.selected(ACCESS_MODE) != mode as u8 {
+            log::warn!("{:?} refused {:x}", mode, status.selected(ACCESS_MODE));
+            return Err(Error::Unsupported);
+        }
+
+        let (speed, strength) = match mode {
+            UhsMode::Sdr12 => (iomuxc::Speed::Medium, iomuxc::DriveStrength::R0_6),
+            UhsMode::Sdr25 | UhsMode::Ddr50 => (iomuxc::Speed::Fast, iomuxc::DriveStrength::R0_7),
+            UhsMode::Sdr50 | UhsMode::Sdr104 => (iomuxc::Speed::Max, iomuxc::DriveStrength::R0_7),
+        };
+        self.pins.set_pad_speed(speed, strength);
+        self.set_sd_clk(mode.max_clock_hz(), mode == UhsMode::Ddr50, ccm);
+        self.mode = CardMode::SdSdioUhsI;
+        log::debug!("{:?} at {} kHz", mode, self.sd_clk_khz);
+
+        if mode.needs_tuning() {
+            self.execute_tuning(TuningCommand::Sd)?;
+        }
+        Ok(())
+    }
+}
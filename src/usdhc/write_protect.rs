@@ -0,0 +1,131 @@
+//! # Write protection
+//!
+//! Two mechanisms protect the user area of a card:
+//!
+//! - group write protection (CMD28/CMD29/CMD30), groups of `wp_grp_size`
+//!   erase sectors, only SDSC cards with WP_GRP_ENABLE in the CSD
+//! - the TMP_WRITE_PROTECT and PERM_WRITE_PROTECT bits of the CSD, programmed
+//!   with CMD27, the permanent one can never be cleared again
+
+use teensy4_bsp::{pins::imxrt_iomuxc::consts::Unsigned, pins::imxrt_iomuxc::usdhc};
+
+use super::{
+    commands,
+    registers::{CardStatus, Csd},
+    transfer::{StopMode, Transfer},
+    Error, USdhc,
+};
+
+/// Command class 6, write protection
+const CCC_WRITE_PROTECTION: u16 = 1 << 6;
+
+/// Confirms setting PERM_WRITE_PROTECT, which turns the card read-only
+/// forever.
+pub struct PermanentWriteProtect(());
+
+impl PermanentWriteProtect {
+    /// The card can't be written, erased or unprotected afterwards
+    pub fn irreversible() -> Self {
+        Self(())
+    }
+}
+
+impl<M, CMD, CLK, D0, D1, D2, D3> USdhc<M, CMD, CLK, D0, D1, D2, D3>
+where
+    M: Unsigned,
+    CMD: usdhc::Pin<Module = M, Signal = usdhc::Cmd>,
+    CLK: usdhc::Pin<Module = M, Signal = usdhc::Clk>,
+    D0: usdhc::Pin<Module = M, Signal = usdhc::Data0>,
+    D1: usdhc::Pin<Module = M, Signal = usdhc::Data1>,
+    D2: usdhc::Pin<Module = M, Signal = usdhc::Data2>,
+    D3: usdhc::Pin<Module = M, Signal = usdhc::Data3>,
+{
+    /// True if the CSD reports a temporary or permanent write protection
+    pub fn is_write_protected(&self) -> bool {
+        match self.csd {
+            Some(csd) => csd.tmp_write_protect() || csd.perm_write_protect(),
+            None => false,
+        }
+    }
+
+    /// Size of a write protect group in blocks, `None` if the card doesn't
+    /// support group write protection
+    pub fn write_protect_group_size(&self) -> Option<u32> {
+        match self.csd {
+            Some(csd) if csd.wp_grp_enable() && csd.command_classes() & CCC_WRITE_PROTECTION != 0 => {
+                Some(csd.sector_size() * csd.wp_grp_size())
+            }
+            _ => None,
+        }
+    }
+
+    /// Protects the group containing `lba` with CMD28
+    pub fn set_write_protect(&mut self, lba: u32) -> Result<(), Error> {
+        let address = self.write_protect_address(lba)?;
+        let status = self.execute(commands::SetWriteProt::new(address))?;
+        CardStatus(status).check()?;
+        self.wait_for_transfer_state()?;
+        Ok(())
+    }
+
+    /// Removes the protection of the group containing `lba` with CMD29
+    pub fn clear_write_protect(&mut self, lba: u32) -> Result<(), Error> {
+        let address = self.write_protect_address(lba)?;
+        let status = self.execute(commands::ClrWriteProt::new(address))?;
+        CardStatus(status).check()?;
+        self.wait_for_transfer_state()?;
+        Ok(())
+    }
+
+    /// Reads the protection bits of 32 groups with CMD30, bit 0 is the group
+    /// containing `lba`. Groups beyond the capacity read as 0.
+    pub fn write_protect_status(&mut self, lba: u32) -> Result<u32, Error> {
+        let address = self.write_protect_address(lba)?;
+        let mut bytes = [0u8; 4];
+        let transfer = Transfer::read(4, 1, StopMode::None);
+        self.read_data(commands::SendWriteProt::new(address), &transfer, &mut bytes)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    /// Sets or clears TMP_WRITE_PROTECT of the CSD with CMD27
+    pub fn set_temporary_write_protect(&mut self, protect: bool) -> Result<(), Error> {
+        let csd = self.csd.ok_or(Error::Unsupported)?;
+        if csd.perm_write_protect() {
+            return Err(Error::Unsupported);
+        }
+        if csd.tmp_write_protect() == protect {
+            return Ok(());
+        }
+        self.program_csd(csd.with_tmp_write_protect(protect))
+    }
+
+    /// Sets PERM_WRITE_PROTECT of the CSD with CMD27, the card is read-only
+    /// afterwards.
+    pub fn set_permanent_write_protect(&mut self, _confirm: PermanentWriteProtect) -> Result<(), Error> {
+        let csd = self.csd.ok_or(Error::Unsupported)?;
+        if csd.perm_write_protect() {
+            return Ok(());
+        }
+        log::warn!("setting PERM_WRITE_PROTECT");
+        self.program_csd(csd.with_perm_write_protect())
+    }
+
+    /// Writes the CSD, the card rejects changes of read-only bits with
+    /// CSD_OVERWRITE. The cached CSD is only updated on success.
+    fn program_csd(&mut self, csd: Csd) -> Result<(), Error> {
+        let bytes = csd.to_bytes();
+        let transfer = Transfer::write(16, 1, StopMode::None);
+        let status = self.write_data(commands::ProgramCsd::new(), &transfer, &bytes)?;
+        status.check()?;
+        self.csd = Some(csd);
+        Ok(())
+    }
+
+    fn write_protect_address(&self, lba: u32) -> Result<u32, Error> {
+        self.write_protect_group_size().ok_or(Error::Unsupported)?;
+        match self.csd {
+            Some(csd) if lba as u64 >= csd.block_count() => Err(Error::InvalidArgument),
+            _ => self.block_address(lba),
+        }
+    }
+}